@@ -4,18 +4,38 @@ const MessageType = {
     QUIT: "quit",
     PRIVATE: "private",
     NICK: "nick",
+    REGISTER: "register",
+    LOGIN: "login",
+    LOGOUT: "logout",
+    OP: "op",
+    KICK: "kick",
+    BAN: "ban",
+    MUTE: "mute",
+    PENDING: "pending",
+    CLEAR_PENDING: "clear_pending",
+    READ: "read",
+    EDIT: "edit",
+    DELETE: "delete",
+    REPLY: "reply",
+    THREAD: "thread",
+    REACT: "react",
+    UNREACT: "unreact",
+    TYPING_START: "typing_start",
+    TYPING_STOP: "typing_stop",
+    STATUS: "status",
+    USERS: "users",
+    IGNORE: "ignore",
+    UNIGNORE: "unignore",
 };
 
 document.addEventListener("DOMContentLoaded", () => {
-    const socket = new WebSocket("ws://localhost:6969");
+    const socket = new WebSocket("ws://localhost:6969/chat", ["chat.v1.json"]);
     const chatMessages = document.querySelector(".chat-messages");
     const messageInput = document.getElementById("messageInput");
     const sendMessageButton = document.getElementById("sendMessage");
+    const typingIndicator = document.querySelector(".typing-indicator");
 
-    const show_message = (text, type) => {
-        const messageElement = document.createElement("div");
-        messageElement.className = `message ${type}`;
-
+    const format_text = (text) => {
         // Escape HTML and handle whitespace
         const escapedText = text
             .replace(/&/g, "&amp;")
@@ -29,9 +49,39 @@ document.addEventListener("DOMContentLoaded", () => {
             .replace(/  /g, "&nbsp;&nbsp;")
             .replace(/\n/g, "<br>");
 
-        messageElement.innerHTML = formattedText;
+        return formattedText;
+    };
+
+    const show_message = (text, type) => {
+        const messageElement = document.createElement("div");
+        messageElement.className = `message ${type}`;
+        messageElement.innerHTML = format_text(text);
         chatMessages.appendChild(messageElement);
         chatMessages.scrollTop = chatMessages.scrollHeight;
+        return messageElement;
+    };
+
+    // Shown chat and private messages by id, to apply edits, deletions and reactions.
+    const shown = new Map();
+    const format_reactions = (reactions) => reactions
+        .map((reaction) => ` ${reaction.emoji} ${reaction.count}`)
+        .join("");
+    const render_identified = (id) => {
+        const message = shown.get(id);
+        const text = `[${id}] ${message.prefix}: ${message.text}`;
+        const reactions = message.reactions.length ? `\n${format_reactions(message.reactions)}` : "";
+        message.element.innerHTML = format_text(text + reactions);
+    };
+    const show_identified = (id, prefix, text, reactions) => {
+        const element = show_message("", "server");
+        shown.set(id, { element, prefix, text, reactions: reactions || [] });
+        render_identified(id);
+    };
+    const replace_identified = (id, changes) => {
+        if (shown.has(id)) {
+            Object.assign(shown.get(id), changes);
+            render_identified(id);
+        }
     };
 
     const send_message = (type, content) => {
@@ -54,6 +104,72 @@ document.addEventListener("DOMContentLoaded", () => {
                 case MessageType.NICK:
                     socket.send(JSON.stringify({message_type: type, nick: content}));
                     break;
+                case MessageType.REGISTER:
+                case MessageType.LOGIN:
+                    let [account, ...password] = content.split(' ');
+                    socket.send(JSON.stringify({ message_type: type, nick: account, password: password.join(' ') }));
+                    break;
+                case MessageType.LOGOUT:
+                    socket.send(JSON.stringify({ message_type: type }));
+                    break;
+                case MessageType.OP:
+                    socket.send(JSON.stringify({ message_type: type, nick: content }));
+                    break;
+                case MessageType.KICK: {
+                    let [nick, ...reason] = content.split(' ');
+                    reason = reason.join(' ');
+                    socket.send(JSON.stringify({ message_type: type, nick: nick, reason: reason || null }));
+                    break;
+                }
+                case MessageType.BAN: {
+                    let [target, duration] = content.split(' ');
+                    socket.send(JSON.stringify({ message_type: type, target: target, duration: duration || null }));
+                    break;
+                }
+                case MessageType.MUTE: {
+                    let [nick, duration] = content.split(' ');
+                    socket.send(JSON.stringify({ message_type: type, nick: nick, duration: duration || null }));
+                    break;
+                }
+                case MessageType.EDIT: {
+                    let [id, ...text] = content.split(' ');
+                    socket.send(JSON.stringify({ message_type: type, id: Number(id), message: text.join(' ') }));
+                    break;
+                }
+                case MessageType.DELETE:
+                case MessageType.THREAD:
+                    socket.send(JSON.stringify({ message_type: type, id: Number(content) }));
+                    break;
+                case MessageType.STATUS: {
+                    let [status, ...text] = content.split(' ');
+                    socket.send(JSON.stringify({ message_type: type, status: status, message: text.join(' ') || null }));
+                    break;
+                }
+                case MessageType.USERS:
+                    socket.send(JSON.stringify({ message_type: type }));
+                    break;
+                case MessageType.IGNORE:
+                case MessageType.UNIGNORE:
+                    socket.send(JSON.stringify({ message_type: type, nick: content || null }));
+                    break;
+                case MessageType.REACT:
+                case MessageType.UNREACT: {
+                    let [id, emoji] = content.split(' ');
+                    socket.send(JSON.stringify({ message_type: type, id: Number(id), emoji: emoji }));
+                    break;
+                }
+                case MessageType.REPLY: {
+                    let [id, ...text] = content.split(' ');
+                    socket.send(JSON.stringify({ message_type: MessageType.CHAT, message: text.join(' '), reply_to: Number(id) }));
+                    break;
+                }
+                case MessageType.READ:
+                    socket.send(JSON.stringify({ message_type: type, ids: content }));
+                    break;
+                case MessageType.PENDING:
+                case MessageType.CLEAR_PENDING:
+                    socket.send(JSON.stringify({ message_type: type }));
+                    break;
             }
         } else {
             show_message("WebSocket is not open.", "server");
@@ -64,9 +180,105 @@ document.addEventListener("DOMContentLoaded", () => {
         show_message("Connected to the chat server.", "server");
     };
 
+    // Read receipts of shown private messages are sent together.
+    let unread = [];
+    const mark_read = (id) => {
+        if (unread.length === 0) {
+            setTimeout(() => {
+                send_message(MessageType.READ, unread);
+                unread = [];
+            }, 500);
+        }
+        unread.push(id);
+    };
+
+    // Who is typing to the chat or to us, shown under the messages.
+    const typing = new Set();
+    const show_typing = () => {
+        const nicks = [...typing];
+        typingIndicator.textContent = nicks.length === 0
+            ? ""
+            : `${nicks.join(", ")} ${nicks.length === 1 ? "is" : "are"} typing…`;
+    };
+
+    const format_status = (user) => {
+        const status = user.status === "dnd" ? "do not disturb" : user.status;
+        return user.message ? `${user.nick} (${status}: ${user.message})` : `${user.nick} (${status})`;
+    };
+
+    const chat_prefix = (message) => {
+        if (message.reply_to) {
+            return `${message.sender} (reply to ${message.reply_to.sender}: "${message.reply_to.message}")`;
+        }
+        return message.sender;
+    };
+
     socket.onmessage = (event) => {
         console.log(event.data);
-        show_message(event.data, "server");
+        const message = JSON.parse(event.data);
+        switch (message.message_type) {
+            case MessageType.CHAT:
+                show_identified(message.id, chat_prefix(message), message.message, message.reactions);
+                break;
+            case MessageType.THREAD:
+                show_message(`Thread ${message.id}:\n` + message.messages
+                    .map((reply) => `[${reply.id}] ${chat_prefix(reply)}: ${reply.message}${format_reactions(reply.reactions)}`)
+                    .join("\n"), "server");
+                break;
+            case MessageType.PRIVATE: {
+                const people = `${message.sender} -> ${message.receiver}`;
+                // Messages that waited for us to log in.
+                if (Date.now() / 1000 - message.sent_at > 60) {
+                    const sent = new Date(message.sent_at * 1000).toLocaleString();
+                    show_identified(message.id, `${people} (private, sent ${sent})`, message.message, message.reactions);
+                } else {
+                    show_identified(message.id, `${people} (private)`, message.message, message.reactions);
+                }
+                mark_read(message.id);
+                break;
+            }
+            case MessageType.EDIT:
+                replace_identified(message.id, { text: `${message.message} (edited by ${message.edited_by})` });
+                break;
+            case MessageType.DELETE:
+                replace_identified(message.id, { text: `(deleted by ${message.deleted_by})`, reactions: [] });
+                break;
+            case "reactions":
+                replace_identified(message.id, { reactions: message.reactions });
+                break;
+            case "typing": {
+                const nick = message.receiver ? `${message.sender} (private)` : message.sender;
+                if (message.typing) {
+                    typing.add(nick);
+                } else {
+                    typing.delete(nick);
+                }
+                show_typing();
+                break;
+            }
+            case MessageType.STATUS:
+                show_message(`Status: ${format_status(message)}`, "server");
+                break;
+            case MessageType.USERS:
+                show_message("Users:\n" + message.users.map(format_status).join("\n"), "server");
+                break;
+            case "mention":
+                // Highlighted even when the chat message itself was missed.
+                if (shown.has(message.id)) {
+                    shown.get(message.id).element.classList.add("mention");
+                } else {
+                    show_message(`${message.sender} mentioned you: ${message.message}`, "mention");
+                }
+                break;
+            case "receipt":
+                show_message(`Private message ${message.id} ${message.status} by ${message.receiver}`, "server");
+                break;
+            case "error":
+                show_message(`Invalid message (${message.code}): ${message.detail}`, "server");
+                break;
+            default:
+                show_message(message.message, "server");
+        }
     };
 
     socket.onerror = (error) => {
@@ -78,6 +290,10 @@ document.addEventListener("DOMContentLoaded", () => {
         show_message("Chat connection closed.", "server");
     };
 
+    // typing_start is repeated while typing, the server ends the notification a few
+    // seconds after the last one.
+    let typing_sent = 0;
+
     sendMessageButton.addEventListener("click", () => {
         const message = messageInput.value.trim();
         if (message) {
@@ -99,18 +315,103 @@ document.addEventListener("DOMContentLoaded", () => {
                 } else if (command === "private") {
                     console.log("Command private");
                     send_message(MessageType.PRIVATE, args);
+                } else if (command === "edit") {
+                    console.log("Command edit");
+                    send_message(MessageType.EDIT, args);
+                } else if (command === "react") {
+                    console.log("Command react");
+                    send_message(MessageType.REACT, args);
+                } else if (command === "unreact") {
+                    console.log("Command unreact");
+                    send_message(MessageType.UNREACT, args);
+                } else if (command === "reply") {
+                    console.log("Command reply");
+                    send_message(MessageType.REPLY, args);
+                } else if (command === "thread") {
+                    console.log("Command thread");
+                    send_message(MessageType.THREAD, args);
+                } else if (command === "delete") {
+                    console.log("Command delete");
+                    send_message(MessageType.DELETE, args);
+                } else if (command === "register") {
+                    console.log("Command register");
+                    send_message(MessageType.REGISTER, args);
+                } else if (command === "login") {
+                    console.log("Command login");
+                    send_message(MessageType.LOGIN, args);
+                } else if (command === "logout") {
+                    console.log("Command logout");
+                    send_message(MessageType.LOGOUT, "");
+                } else if (command === "op") {
+                    console.log("Command op");
+                    send_message(MessageType.OP, args);
+                } else if (command === "kick") {
+                    console.log("Command kick");
+                    send_message(MessageType.KICK, args);
+                } else if (command === "ban") {
+                    console.log("Command ban");
+                    send_message(MessageType.BAN, args);
+                } else if (command === "mute") {
+                    console.log("Command mute");
+                    send_message(MessageType.MUTE, args);
+                } else if (command === "away" || command === "dnd") {
+                    console.log(`Command ${command}`);
+                    send_message(MessageType.STATUS, `${command} ${args}`);
+                } else if (command === "back") {
+                    console.log("Command back");
+                    send_message(MessageType.STATUS, "online");
+                } else if (command === "status") {
+                    console.log("Command status");
+                    send_message(MessageType.STATUS, `online ${args}`);
+                } else if (command === "users") {
+                    console.log("Command users");
+                    send_message(MessageType.USERS, "");
+                } else if (command === "ignore") {
+                    console.log("Command ignore");
+                    send_message(MessageType.IGNORE, args);
+                    // Their typing_stop will not reach us anymore.
+                    typing.delete(args);
+                    show_typing();
+                } else if (command === "unignore") {
+                    console.log("Command unignore");
+                    send_message(MessageType.UNIGNORE, args);
+                } else if (command === "pending") {
+                    console.log("Command pending");
+                    send_message(args === "clear" ? MessageType.CLEAR_PENDING : MessageType.PENDING, "");
                 } else {
                     console.log("Unknown command");
                     show_message(`Unknown command: ${command}`, "server");
                 }
             } else {
                 console.log("Normal message");
+                // Shown once the server echoes it back with its id.
                 send_message(MessageType.CHAT, message);
-                show_message(message, "user");
             }
 
         }
         messageInput.value = ""; // Clear the input
+        // The server ends the typing notification itself when the message arrives.
+        typing_sent = 0;
+    });
+
+    messageInput.addEventListener("input", () => {
+        const text = messageInput.value;
+        if (text === "") {
+            if (typing_sent) {
+                socket.send(JSON.stringify({ message_type: MessageType.TYPING_STOP }));
+                typing_sent = 0;
+            }
+            return;
+        }
+        const private_to = text.match(/^\/private (\S+) /);
+        if ((text.startsWith("/") && !private_to) || Date.now() - typing_sent < 3000) {
+            return;
+        }
+        socket.send(JSON.stringify({
+            message_type: MessageType.TYPING_START,
+            receiver: private_to ? private_to[1] : null,
+        }));
+        typing_sent = Date.now();
     });
 
     messageInput.addEventListener("keydown", (event) => {
@@ -3,54 +3,153 @@ mod ws;
 use std::env;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
 
+use ws::chat_handler::{ChatHandler, DEFAULT_AWAY_AFTER, DEFAULT_EDIT_WINDOW};
+use ws::compression::ResponseCompressor;
+use ws::connection_limit::ConnectionLimits;
 use ws::file_storage::FileStorage;
 use ws::http_router::HttpRouter;
+use ws::ignore_list::IgnoreList;
+use ws::login_handler::{LoginHandler, LogoutHandler, DEFAULT_LOGIN_LIMIT};
 use ws::method::Method;
 use ws::middleware::{Middleware, RequestLogger};
+use ws::moderation::{parse_duration, BanList, Moderation};
+use ws::offline_queue::OfflineQueue;
+use ws::rate_limit::{IpRateLimiter, RateLimit, RateLimits};
+use ws::session_store::SessionStore;
 use ws::static_file_handler::StaticFileHandler;
-use ws::ws_server::WsServer;
+use ws::token_auth::TokenValidator;
+use ws::user_store::{FileUserStore, UserStore};
+use ws::ws_server::{default_websocket_config, WsServer};
 
 #[tokio::main]
 async fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: WebsocketRustChat <doc_root>");
+    if args.len() != 2 && args.len() != 3 {
+        eprintln!("Usage: WebsocketRustChat <doc_root> [data_dir]");
         std::process::exit(1);
     }
     let doc_root_path = Path::new(&args[1]);
+    let data_dir_path = Path::new(args.get(2).map_or("data", |dir| dir.as_str()));
 
-    let file_storage = if let Some(file_storage) = FileStorage::new(doc_root_path) {
+    let mut file_storage = if let Some(file_storage) = FileStorage::new(doc_root_path) {
         file_storage
     } else {
         eprintln!("Cuuld not load files from provided directory");
         std::process::exit(1);
     };
 
+    // Comma separated "<extension>=<mime type>" pairs added to the built-in table.
+    if let Ok(mime_types) = env::var("CHAT_MIME_TYPES") {
+        for entry in mime_types
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+        {
+            match entry.split_once('=') {
+                Some((extension, mime))
+                    if !extension.trim().is_empty() && !mime.trim().is_empty() =>
+                {
+                    file_storage.register_mime_type(extension.trim(), mime.trim());
+                }
+                _ => eprintln!("Invalid mime type {:?} in CHAT_MIME_TYPES", entry),
+            }
+        }
+    }
+
+    // Precompressing trades startup time and memory for cheaper static responses.
+    if env_flag("CHAT_PRECOMPRESS", true) {
+        file_storage.precompress();
+    }
     let file_storage = Arc::new(file_storage);
 
+    let user_store: Arc<dyn UserStore + Send + Sync> =
+        match FileUserStore::new(&data_dir_path.join("users.txt")) {
+            Ok(user_store) => Arc::new(user_store),
+            Err(e) => {
+                eprintln!("Could not load user accounts, error: {}", e);
+                std::process::exit(1);
+            }
+        };
+    let bans = match BanList::new(&data_dir_path.join("bans.txt")) {
+        Ok(bans) => Arc::new(bans),
+        Err(e) => {
+            eprintln!("Could not load bans, error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // Comma separated nicknames that are operators once logged in.
+    let operators = env::var("CHAT_OPERATORS")
+        .map(|operators| {
+            operators
+                .split(',')
+                .map(|nick| nick.trim().to_string())
+                .filter(|nick| !nick.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    let moderation = Arc::new(Moderation::new(bans.clone(), operators));
+    // How long private messages wait for an offline receiver.
+    let offline_ttl = env_duration(
+        "CHAT_OFFLINE_MESSAGE_TTL",
+        Duration::from_secs(7 * 24 * 60 * 60),
+    );
+    let offline_queue = match OfflineQueue::new(&data_dir_path.join("messages.txt"), offline_ttl) {
+        Ok(offline_queue) => Arc::new(offline_queue),
+        Err(e) => {
+            eprintln!("Could not load pending messages, error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let ignore_list = match IgnoreList::new(&data_dir_path.join("ignores.txt")) {
+        Ok(ignore_list) => Arc::new(ignore_list),
+        Err(e) => {
+            eprintln!("Could not load ignore lists, error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let sessions = Arc::new(SessionStore::new(Duration::from_secs(24 * 60 * 60)));
+
+    let login_limiter = Arc::new(IpRateLimiter::new(env_rate_limit(
+        "CHAT_RATE_LOGIN",
+        DEFAULT_LOGIN_LIMIT,
+    )));
+
+    let mut chat_handler = ChatHandler::new(user_store.clone(), moderation);
+    chat_handler
+        .set_rate_limits(rate_limits_from_env())
+        .set_login_limiter(login_limiter.clone())
+        .set_offline_queue(offline_queue)
+        .set_ignore_list(ignore_list)
+        .set_edit_window(env_duration("CHAT_EDIT_WINDOW", DEFAULT_EDIT_WINDOW))
+        .set_away_after(env_duration("CHAT_AWAY_AFTER", DEFAULT_AWAY_AFTER));
+
     let mut http_router = HttpRouter::new(file_storage.clone());
     http_router
         .add_route(
             Method::Get,
             String::from("/"),
-            Middleware::new(
+            ResponseCompressor::new(Middleware::new(
                 RequestLogger::new(),
                 StaticFileHandler::new(file_storage.clone(), String::from("index.html")),
-            ),
+            )),
         )
         .add_route(
             Method::Get,
             String::from("/index.html"),
-            Middleware::new(
+            ResponseCompressor::new(Middleware::new(
                 RequestLogger::new(),
                 StaticFileHandler::new(file_storage.clone(), String::from("index.html")),
-            ),
+            )),
         )
         .add_route(
             Method::Get,
             String::from("/script.js"),
-            StaticFileHandler::new(file_storage.clone(), String::from("script.js")),
+            ResponseCompressor::new(StaticFileHandler::new(
+                file_storage.clone(),
+                String::from("script.js"),
+            )),
         )
         .add_route(
             Method::Get,
@@ -59,7 +158,171 @@ async fn main() {
                 RequestLogger::new(),
                 StaticFileHandler::new(file_storage.clone(), String::from("favicon.png")),
             ),
-        );
+        )
+        .add_route(
+            Method::Get,
+            String::from("/login.html"),
+            ResponseCompressor::new(StaticFileHandler::new(
+                file_storage.clone(),
+                String::from("login.html"),
+            )),
+        )
+        .add_route(
+            Method::Post,
+            String::from("/login"),
+            Middleware::new(
+                RequestLogger::new(),
+                LoginHandler::new(
+                    user_store.clone(),
+                    sessions.clone(),
+                    String::from("/"),
+                    login_limiter,
+                ),
+            ),
+        )
+        .add_route(
+            Method::Post,
+            String::from("/logout"),
+            Middleware::new(
+                RequestLogger::new(),
+                LogoutHandler::new(sessions.clone(), String::from("/login.html")),
+            ),
+        )
+        .add_ws_route(String::from("/chat"), chat_handler)
+        .set_session_store(sessions)
+        .set_allowed_origins(env_list(
+            "CHAT_ALLOWED_ORIGINS",
+            &["http://localhost:6969", "http://127.0.0.1:6969"],
+        ));
+
+    if let Ok(secret) = env::var("CHAT_TOKEN_SECRET") {
+        let required = env::var("CHAT_TOKEN_REQUIRED").is_ok_and(|value| value == "1");
+        http_router.set_token_auth(TokenValidator::new(secret.as_bytes()), required);
+    }
+
+    let mut server = WsServer::new(http_router);
+    server
+        .set_ban_list(bans)
+        .set_connection_limits(connection_limits_from_env())
+        .set_websocket_config(websocket_config_from_env());
+    server.start("localhost:6969").await;
+}
+
+// Rate limits are overridden with "<burst>/<per second>" values, e.g. CHAT_RATE_CHAT=5/1.
+fn rate_limits_from_env() -> RateLimits {
+    let defaults = RateLimits::default();
+    RateLimits {
+        chat: env_rate_limit("CHAT_RATE_CHAT", defaults.chat),
+        private: env_rate_limit("CHAT_RATE_PRIVATE", defaults.private),
+        command: env_rate_limit("CHAT_RATE_COMMAND", defaults.command),
+        typing: env_rate_limit("CHAT_RATE_TYPING", defaults.typing),
+        global: env_rate_limit("CHAT_RATE_GLOBAL", defaults.global),
+        ..defaults
+    }
+}
+
+fn env_rate_limit(name: &str, default: RateLimit) -> RateLimit {
+    match env::var(name) {
+        Ok(value) => RateLimit::parse(&value).unwrap_or_else(|| {
+            eprintln!("Invalid rate limit {}={}, using default", name, value);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+fn connection_limits_from_env() -> ConnectionLimits {
+    let defaults = ConnectionLimits::default();
+    ConnectionLimits {
+        max_connections: env_size("CHAT_MAX_CONNECTIONS", defaults.max_connections),
+        max_connections_per_ip: env_size(
+            "CHAT_MAX_CONNECTIONS_PER_IP",
+            defaults.max_connections_per_ip,
+        ),
+        max_websockets_per_ip: env_size(
+            "CHAT_MAX_WEBSOCKETS_PER_IP",
+            defaults.max_websockets_per_ip,
+        ),
+    }
+}
+
+// Sizes are in bytes.
+fn websocket_config_from_env() -> WebSocketConfig {
+    let defaults = default_websocket_config();
+    let config = WebSocketConfig {
+        max_message_size: Some(env_size(
+            "CHAT_MAX_MESSAGE_SIZE",
+            defaults.max_message_size.unwrap_or(usize::MAX),
+        )),
+        max_frame_size: Some(env_size(
+            "CHAT_MAX_FRAME_SIZE",
+            defaults.max_frame_size.unwrap_or(usize::MAX),
+        )),
+        write_buffer_size: env_size("CHAT_WRITE_BUFFER_SIZE", defaults.write_buffer_size),
+        max_write_buffer_size: env_size(
+            "CHAT_MAX_WRITE_BUFFER_SIZE",
+            defaults.max_write_buffer_size,
+        ),
+        ..defaults
+    };
 
-    WsServer::new(http_router).start("localhost:6969").await;
+    if config.max_write_buffer_size <= config.write_buffer_size {
+        eprintln!("CHAT_MAX_WRITE_BUFFER_SIZE must be greater than CHAT_WRITE_BUFFER_SIZE");
+        std::process::exit(1);
+    }
+    config
+}
+
+// Durations are seconds or a number with s, m, h or d suffix, e.g. CHAT_EDIT_WINDOW=15m.
+fn env_duration(name: &str, default: Duration) -> Duration {
+    match env::var(name) {
+        Ok(value) => parse_duration(&value).unwrap_or_else(|| {
+            eprintln!("Invalid duration {}={}, using default", name, value);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+fn env_flag(name: &str, default: bool) -> bool {
+    match env::var(name).as_deref() {
+        Ok("1" | "true" | "on") => true,
+        Ok("0" | "false" | "off") => false,
+        Ok(value) => {
+            eprintln!("Invalid flag {}={}, using default", name, value);
+            default
+        }
+        Err(_) => default,
+    }
+}
+
+// Comma separated, e.g. CHAT_ALLOWED_ORIGINS=https://chat.example,https://example.
+fn env_list(name: &str, default: &[&str]) -> Vec<String> {
+    let default = || default.iter().map(|item| item.to_string()).collect();
+    match env::var(name) {
+        Ok(value) => {
+            let list: Vec<String> = value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect();
+            if list.is_empty() {
+                eprintln!("Invalid list {}={}, using default", name, value);
+                return default();
+            }
+            list
+        }
+        Err(_) => default(),
+    }
+}
+
+fn env_size(name: &str, default: usize) -> usize {
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid value {}={}, using default", name, value);
+            default
+        }),
+        Err(_) => default,
+    }
 }
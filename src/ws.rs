@@ -1,14 +1,37 @@
+pub mod chat_handler;
+pub mod codec;
+pub mod compression;
+pub mod connection_limit;
 pub mod file_storage;
 pub mod handler;
+pub mod http_cookie;
 pub mod http_header;
+pub mod http_range;
 pub mod http_request;
 mod http_request_parser;
 pub mod http_response;
 pub mod http_router;
 mod http_session;
+pub mod identity;
+pub mod ignore_list;
+pub mod line_file;
+pub mod login_handler;
+pub mod mentions;
+pub mod message_log;
 pub mod method;
 pub mod middleware;
+pub mod mime;
+pub mod moderation;
+pub mod offline_queue;
+pub mod rate_limit;
+pub mod receipts;
+pub mod session_store;
 pub mod static_file_handler;
+#[cfg(test)]
+mod test_util;
+pub mod token_auth;
+pub mod user_store;
+pub mod ws_handler;
 pub mod ws_message;
 pub mod ws_server;
 mod ws_session;
@@ -0,0 +1,113 @@
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use crate::ws::codec::Codec;
+use crate::ws::ignore_list::IgnoreList;
+use crate::ws::login_handler::DEFAULT_LOGIN_LIMIT;
+use crate::ws::message_log::MessageLog;
+use crate::ws::moderation::Moderation;
+use crate::ws::offline_queue::OfflineQueue;
+use crate::ws::rate_limit::{IpRateLimiter, RateLimits, TokenBucket};
+use crate::ws::receipts::{MessageIds, ReadReceipts};
+use crate::ws::user_store::UserStore;
+use crate::ws::ws_handler::{WsContext, WsHandler, WsStream};
+use crate::ws::ws_session::{ChatState, WsSession};
+
+pub const CHAT_PROTOCOLS: [&str; 2] = ["chat.v1.json", "chat.v1.msgpack"];
+pub const DEFAULT_EDIT_WINDOW: Duration = Duration::from_secs(15 * 60);
+pub const DEFAULT_AWAY_AFTER: Duration = Duration::from_secs(10 * 60);
+
+pub struct ChatHandler {
+    state: ChatState,
+}
+
+impl ChatHandler {
+    pub fn new(users: Arc<dyn UserStore + Send + Sync>, moderation: Arc<Moderation>) -> Self {
+        let rate_limits = RateLimits::default();
+        Self {
+            state: ChatState {
+                clients: Arc::new(Mutex::new(HashMap::new())),
+                users,
+                moderation,
+                offline_queue: None,
+                ignore_list: None,
+                message_ids: Arc::new(MessageIds::new()),
+                receipts: Arc::new(ReadReceipts::new()),
+                message_log: Arc::new(MessageLog::new()),
+                edit_window: DEFAULT_EDIT_WINDOW,
+                away_after: DEFAULT_AWAY_AFTER,
+                rate_limits,
+                global_limit: Arc::new(std::sync::Mutex::new(TokenBucket::new(rate_limits.global))),
+                login_limiter: Arc::new(IpRateLimiter::new(DEFAULT_LOGIN_LIMIT)),
+            },
+        }
+    }
+
+    pub fn set_rate_limits(&mut self, rate_limits: RateLimits) -> &mut Self {
+        self.state.rate_limits = rate_limits;
+        self.state.global_limit =
+            Arc::new(std::sync::Mutex::new(TokenBucket::new(rate_limits.global)));
+        self
+    }
+
+    // Share it with the login form, so both count the same attempts of an address.
+    pub fn set_login_limiter(&mut self, login_limiter: Arc<IpRateLimiter>) -> &mut Self {
+        self.state.login_limiter = login_limiter;
+        self
+    }
+
+    // How long after sending a message its author can edit or delete it.
+    pub fn set_edit_window(&mut self, window: Duration) -> &mut Self {
+        self.state.edit_window = window;
+        self
+    }
+
+    // How long a user has to be inactive to be marked away, zero never does.
+    pub fn set_away_after(&mut self, away_after: Duration) -> &mut Self {
+        self.state.away_after = away_after;
+        self
+    }
+
+    // Private messages to registered nicknames that are offline wait here until they
+    // log in, without a queue they are rejected.
+    pub fn set_offline_queue(&mut self, offline_queue: Arc<OfflineQueue>) -> &mut Self {
+        self.state.offline_queue = Some(offline_queue);
+        self
+    }
+
+    // Keeps the nicknames registered users ignore across sessions, without it they are
+    // forgotten when the user leaves.
+    pub fn set_ignore_list(&mut self, ignore_list: Arc<IgnoreList>) -> &mut Self {
+        self.state.ignore_list = Some(ignore_list);
+        self
+    }
+}
+
+impl WsHandler for ChatHandler {
+    fn handle(&self, context: WsContext, socket: WsStream) -> BoxFuture<'static, ()> {
+        let state = self.state.clone();
+        Box::pin(async move {
+            println!(
+                "New chat connection on {}, protocol: {}",
+                context.request.path(),
+                context.protocol.as_deref().unwrap_or("none")
+            );
+            let ip = socket.get_ref().peer_addr().ok().map(|addr| addr.ip());
+            let codec = context.protocol.as_deref().and_then(Codec::from_protocol);
+            if let Some(mut ws_session) =
+                WsSession::new(socket, state, context.user, ip, codec).await
+            {
+                ws_session.handle_ws_connection().await;
+            } else {
+                eprintln!("Could not accept websocket connection");
+            }
+        })
+    }
+
+    fn protocols(&self) -> &[&'static str] {
+        &CHAT_PROTOCOLS
+    }
+}
@@ -0,0 +1,452 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::ws::ws_message::{ErrorCode, ErrorEvent, MessageType};
+
+#[derive(Debug)]
+pub enum CodecError {
+    // Text frame on a MessagePack connection or binary frame on a JSON one.
+    UnexpectedFrame,
+    Json(serde_json::Error),
+    MessagePackDecode(rmp_serde::decode::Error),
+    MessagePackEncode(rmp_serde::encode::Error),
+}
+
+impl std::fmt::Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedFrame => write!(f, "unexpected frame type"),
+            Self::Json(e) => write!(f, "{}", e),
+            Self::MessagePackDecode(e) => write!(f, "{}", e),
+            Self::MessagePackEncode(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// Just the tag of a command, to explain why the whole command did not parse.
+#[derive(Deserialize)]
+struct CommandTag {
+    message_type: Option<String>,
+}
+
+// Wire format of a chat connection, JSON in text frames or MessagePack in binary ones.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Codec {
+    Json,
+    MessagePack,
+}
+
+impl Codec {
+    // Ordered by preference of the server.
+    pub const ALL: [Codec; 2] = [Self::Json, Self::MessagePack];
+
+    pub fn protocol(&self) -> &'static str {
+        match self {
+            Self::Json => "chat.v1.json",
+            Self::MessagePack => "chat.v1.msgpack",
+        }
+    }
+
+    pub fn from_protocol(protocol: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|codec| codec.protocol() == protocol)
+    }
+
+    // Clients that did not negotiate a subprotocol are recognized by their first frame.
+    pub fn detect(message: &Message) -> Option<Self> {
+        match message {
+            Message::Text(_) => Some(Self::Json),
+            Message::Binary(_) => Some(Self::MessagePack),
+            _ => None,
+        }
+    }
+
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Message, CodecError> {
+        match self {
+            Self::Json => serde_json::to_string(value)
+                .map(Message::Text)
+                .map_err(CodecError::Json),
+            // Maps keyed by field name, the internally tagged enums need them.
+            Self::MessagePack => rmp_serde::to_vec_named(value)
+                .map(Message::Binary)
+                .map_err(CodecError::MessagePackEncode),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, message: &Message) -> Result<T, CodecError> {
+        match (self, message) {
+            (Self::Json, Message::Text(text)) => {
+                serde_json::from_str(text).map_err(CodecError::Json)
+            }
+            (Self::MessagePack, Message::Binary(data)) => {
+                rmp_serde::from_slice(data).map_err(CodecError::MessagePackDecode)
+            }
+            _ => Err(CodecError::UnexpectedFrame),
+        }
+    }
+
+    pub fn decode_command(&self, message: &Message) -> Result<MessageType, ErrorEvent> {
+        let error = match self.decode::<MessageType>(message) {
+            Ok(command) => return Ok(command),
+            Err(e) => e,
+        };
+
+        let error_event = |code, command, detail: String| ErrorEvent {
+            code,
+            command,
+            detail,
+        };
+        if let CodecError::UnexpectedFrame = error {
+            return Err(error_event(
+                ErrorCode::UnexpectedFrame,
+                None,
+                format!("expected {} frames", self.frame_type()),
+            ));
+        }
+
+        match self.decode::<CommandTag>(message) {
+            Err(e) => Err(error_event(ErrorCode::Malformed, None, e.to_string())),
+            Ok(CommandTag { message_type: None }) => Err(error_event(
+                ErrorCode::MissingMessageType,
+                None,
+                String::from("missing field `message_type`"),
+            )),
+            Ok(CommandTag {
+                message_type: Some(message_type),
+            }) if !MessageType::NAMES.contains(&message_type.as_str()) => Err(error_event(
+                ErrorCode::UnknownMessageType,
+                Some(message_type),
+                format!("expected one of {}", MessageType::NAMES.join(", ")),
+            )),
+            Ok(CommandTag { message_type }) => Err(error_event(
+                ErrorCode::InvalidFields,
+                message_type,
+                error.to_string(),
+            )),
+        }
+    }
+
+    fn frame_type(&self) -> &'static str {
+        match self {
+            Self::Json => "text",
+            Self::MessagePack => "binary",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::ws_message::*;
+
+    fn client_messages() -> Vec<MessageType> {
+        vec![
+            MessageType::Nick(NickMessage {
+                nick: String::from("alice"),
+            }),
+            MessageType::Private(PrivateMessage {
+                receiver: String::from("bob"),
+                message: String::from("hi bob"),
+            }),
+            MessageType::Chat(ChatMessage {
+                message: String::from("hello everyone"),
+                reply_to: None,
+            }),
+            MessageType::Chat(ChatMessage {
+                message: String::from("hello alice"),
+                reply_to: Some(1),
+            }),
+            MessageType::Help(HelpMessage {}),
+            MessageType::Quit(QuitMessage {}),
+            MessageType::Register(RegisterMessage {
+                nick: String::from("alice"),
+                password: String::from("correct horse"),
+            }),
+            MessageType::Login(LoginMessage {
+                nick: String::from("alice"),
+                password: String::from("correct horse"),
+            }),
+            MessageType::Logout(LogoutMessage {}),
+            MessageType::Op(OpMessage {
+                nick: String::from("bob"),
+            }),
+            MessageType::Kick(KickMessage {
+                nick: String::from("bob"),
+                reason: Some(String::from("spam")),
+            }),
+            MessageType::Kick(KickMessage {
+                nick: String::from("bob"),
+                reason: None,
+            }),
+            MessageType::Ban(BanMessage {
+                target: String::from("10.0.0.1"),
+                duration: Some(String::from("1h")),
+            }),
+            MessageType::Mute(MuteMessage {
+                nick: String::from("bob"),
+                duration: None,
+            }),
+            MessageType::Pending(PendingMessagesMessage {}),
+            MessageType::ClearPending(ClearPendingMessage {}),
+            MessageType::Read(ReadMessage { ids: vec![1, 2] }),
+            MessageType::Edit(EditMessage {
+                id: 1,
+                message: String::from("hello"),
+            }),
+            MessageType::Delete(DeleteMessage { id: 1 }),
+            MessageType::Thread(ThreadMessage { id: 1 }),
+            MessageType::React(ReactMessage {
+                id: 1,
+                emoji: String::from("🎉"),
+            }),
+            MessageType::Unreact(UnreactMessage {
+                id: 1,
+                emoji: String::from(":+1:"),
+            }),
+            MessageType::TypingStart(TypingStartMessage { receiver: None }),
+            MessageType::TypingStart(TypingStartMessage {
+                receiver: Some(String::from("bob")),
+            }),
+            MessageType::TypingStop(TypingStopMessage {}),
+            MessageType::Status(StatusMessage {
+                status: Status::Away,
+                message: Some(String::from("lunch")),
+            }),
+            MessageType::Users(UsersMessage {}),
+            MessageType::Ignore(IgnoreMessage {
+                nick: Some(String::from("mallory")),
+            }),
+            MessageType::Ignore(IgnoreMessage { nick: None }),
+            MessageType::Unignore(UnignoreMessage {
+                nick: String::from("mallory"),
+            }),
+        ]
+    }
+
+    fn server_messages() -> Vec<ServerMessage> {
+        vec![
+            ServerMessage::Notice(NoticeEvent {
+                message: String::from("Hello alice, now you can send messages"),
+            }),
+            ServerMessage::Chat(ChatEvent {
+                id: 1,
+                sender: String::from("alice"),
+                message: String::from("hello everyone"),
+                sent_at: 1_700_000_000,
+                reply_to: None,
+                thread: None,
+                reactions: Vec::new(),
+                mentions: vec![String::from("bob")],
+            }),
+            ServerMessage::Thread(ThreadEvent {
+                id: 1,
+                messages: vec![ChatEvent {
+                    id: 2,
+                    sender: String::from("bob"),
+                    message: String::from("hello alice"),
+                    sent_at: 1_700_000_000,
+                    reply_to: Some(Quote {
+                        id: 1,
+                        sender: String::from("alice"),
+                        message: String::from("hello everyone"),
+                    }),
+                    thread: Some(1),
+                    reactions: vec![Reaction {
+                        emoji: String::from(":+1:"),
+                        count: 1,
+                        nicks: vec![String::from("alice")],
+                    }],
+                    mentions: Vec::new(),
+                }],
+            }),
+            ServerMessage::Private(PrivateEvent {
+                id: 1,
+                sender: String::from("alice"),
+                receiver: String::from("bob"),
+                message: String::from("hi bob"),
+                sent_at: 1_700_000_000,
+                reactions: Vec::new(),
+            }),
+            ServerMessage::Private(PrivateEvent {
+                id: 2,
+                sender: String::from("alice"),
+                receiver: String::from("bob"),
+                message: String::from("hi bob"),
+                sent_at: 1_700_000_000,
+                reactions: Vec::new(),
+            }),
+            ServerMessage::Receipt(ReceiptEvent {
+                id: 1,
+                receiver: String::from("bob"),
+                status: ReceiptStatus::Read,
+            }),
+            ServerMessage::Edit(EditEvent {
+                id: 1,
+                message: String::from("hello everyone"),
+                edited_by: String::from("alice"),
+            }),
+            ServerMessage::Reactions(ReactionsEvent {
+                id: 1,
+                reactions: Vec::new(),
+            }),
+            ServerMessage::Mention(MentionEvent {
+                id: 1,
+                sender: String::from("alice"),
+                message: String::from("hello @bob"),
+            }),
+            ServerMessage::Typing(TypingEvent {
+                sender: String::from("alice"),
+                receiver: None,
+                typing: true,
+            }),
+            ServerMessage::Status(UserStatus {
+                nick: String::from("alice"),
+                status: Status::Dnd,
+                message: None,
+            }),
+            ServerMessage::Users(UsersEvent {
+                users: vec![UserStatus {
+                    nick: String::from("alice"),
+                    status: Status::Online,
+                    message: Some(String::from("working")),
+                }],
+            }),
+            ServerMessage::Delete(DeleteEvent {
+                id: 1,
+                deleted_by: String::from("admin"),
+            }),
+            ServerMessage::Error(ErrorEvent {
+                code: ErrorCode::UnknownMessageType,
+                command: Some(String::from("whisper")),
+                detail: String::from("expected one of nick, private"),
+            }),
+        ]
+    }
+
+    #[test]
+    fn test_round_trip_client_messages() {
+        for codec in Codec::ALL {
+            for message in client_messages() {
+                let encoded = codec.encode(&message).unwrap();
+                assert_eq!(codec.decode::<MessageType>(&encoded).unwrap(), message);
+            }
+        }
+    }
+
+    #[test]
+    fn test_message_type_names() {
+        for message in client_messages() {
+            let encoded = serde_json::to_value(&message).unwrap();
+            let name = encoded["message_type"].as_str().unwrap();
+            assert!(MessageType::NAMES.contains(&name), "{} is not listed", name);
+        }
+    }
+
+    #[test]
+    fn test_decode_command_errors() {
+        let decode = |text: &str| {
+            Codec::Json
+                .decode_command(&Message::Text(text.to_string()))
+                .unwrap_err()
+        };
+
+        assert_eq!(decode("not json").code, ErrorCode::Malformed);
+        assert_eq!(
+            decode(r#"{"message":"hi"}"#).code,
+            ErrorCode::MissingMessageType
+        );
+
+        let unknown = decode(r#"{"message_type":"whisper"}"#);
+        assert_eq!(unknown.code, ErrorCode::UnknownMessageType);
+        assert_eq!(unknown.command.as_deref(), Some("whisper"));
+
+        let invalid = decode(r#"{"message_type":"private","message":"hi"}"#);
+        assert_eq!(invalid.code, ErrorCode::InvalidFields);
+        assert_eq!(invalid.command.as_deref(), Some("private"));
+        assert!(invalid.detail.contains("receiver"), "{}", invalid.detail);
+
+        let binary = Codec::MessagePack.encode(&MessageType::Help(HelpMessage {}));
+        assert_eq!(
+            Codec::Json
+                .decode_command(&binary.unwrap())
+                .unwrap_err()
+                .code,
+            ErrorCode::UnexpectedFrame
+        );
+    }
+
+    #[test]
+    fn test_decode_message_pack_command_errors() {
+        #[derive(Serialize)]
+        struct Incomplete {
+            message_type: &'static str,
+        }
+        let encoded = Codec::MessagePack
+            .encode(&Incomplete {
+                message_type: "nick",
+            })
+            .unwrap();
+
+        let error = Codec::MessagePack.decode_command(&encoded).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidFields);
+        assert!(error.detail.contains("nick"), "{}", error.detail);
+    }
+
+    #[test]
+    fn test_round_trip_server_messages() {
+        for codec in Codec::ALL {
+            for message in server_messages() {
+                let encoded = codec.encode(&message).unwrap();
+                assert_eq!(codec.decode::<ServerMessage>(&encoded).unwrap(), message);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_json_sent_by_clients() {
+        let message = Message::Text(String::from(
+            r#"{"message_type":"private","receiver":"bob","message":"hi"}"#,
+        ));
+
+        assert_eq!(
+            Codec::Json.decode::<MessageType>(&message).unwrap(),
+            MessageType::Private(PrivateMessage {
+                receiver: String::from("bob"),
+                message: String::from("hi"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_reply_to_is_optional() {
+        let message = Message::Text(String::from(r#"{"message_type":"chat","message":"hi"}"#));
+
+        assert_eq!(
+            Codec::Json.decode::<MessageType>(&message).unwrap(),
+            MessageType::Chat(ChatMessage {
+                message: String::from("hi"),
+                reply_to: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_reject_unexpected_frame() {
+        let text = Codec::Json
+            .encode(&MessageType::Help(HelpMessage {}))
+            .unwrap();
+
+        assert!(matches!(
+            Codec::MessagePack.decode::<MessageType>(&text),
+            Err(CodecError::UnexpectedFrame)
+        ));
+        assert_eq!(Codec::detect(&text), Some(Codec::Json));
+        assert_eq!(
+            Codec::from_protocol("chat.v1.msgpack"),
+            Some(Codec::MessagePack)
+        );
+    }
+}
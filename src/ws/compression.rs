@@ -0,0 +1,362 @@
+use std::io::Write;
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+
+use crate::ws::handler::Handler;
+use crate::ws::http_request::HttpRequest;
+use crate::ws::http_response::{HttpResponse, StatusType};
+
+// Bodies smaller than this are sent as they are, the encoding overhead is not worth it.
+const MIN_COMPRESS_SIZE: usize = 256;
+
+const BROTLI_QUALITY: u32 = 9;
+const BROTLI_WINDOW: u32 = 22;
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum ContentCoding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl ContentCoding {
+    // Ordered by preference, used to break ties between equal q-values.
+    pub const ALL: [ContentCoding; 3] = [Self::Brotli, Self::Gzip, Self::Deflate];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Brotli => {
+                let mut writer =
+                    brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+                writer
+                    .write_all(data)
+                    .expect("Writing to memory should not fail");
+                writer.into_inner()
+            }
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder
+                    .write_all(data)
+                    .expect("Writing to memory should not fail");
+                encoder.finish().expect("Writing to memory should not fail")
+            }
+            Self::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder
+                    .write_all(data)
+                    .expect("Writing to memory should not fail");
+                encoder.finish().expect("Writing to memory should not fail")
+            }
+        }
+    }
+}
+
+// Picks the best coding from `available` according to the Accept-Encoding q-values.
+// None means the identity representation should be sent.
+pub fn negotiate(accept_encoding: &str, available: &[ContentCoding]) -> Option<ContentCoding> {
+    let mut explicit = Vec::new();
+    let mut wildcard = None;
+
+    for entry in accept_encoding.split(',') {
+        let mut parts = entry.split(';');
+        let name = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        if name.is_empty() {
+            continue;
+        }
+
+        let mut quality = 1.0;
+        for param in parts {
+            if let Some((key, value)) = param.split_once('=') {
+                if key.trim().eq_ignore_ascii_case("q") {
+                    quality = value.trim().parse::<f32>().unwrap_or(0.0);
+                }
+            }
+        }
+
+        if name == "*" {
+            wildcard = Some(quality);
+        } else {
+            explicit.push((name, quality));
+        }
+    }
+
+    let mut best: Option<(ContentCoding, f32)> = None;
+    for coding in ContentCoding::ALL {
+        if !available.contains(&coding) {
+            continue;
+        }
+
+        let quality = explicit
+            .iter()
+            .find(|(name, _)| name == coding.name())
+            .map(|(_, quality)| *quality)
+            .or(wildcard)
+            .unwrap_or(0.0);
+
+        if quality > 0.0 && best.is_none_or(|(_, best_quality)| quality > best_quality) {
+            best = Some((coding, quality));
+        }
+    }
+
+    best.map(|(coding, _)| coding)
+}
+
+pub fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || matches!(
+            mime.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "image/svg+xml"
+                | "image/x-icon"
+                | "image/vnd.microsoft.icon"
+        )
+}
+
+pub fn encoded_etag(etag: &str, coding: ContentCoding) -> String {
+    format!("\"{}-{}\"", etag.trim_matches('"'), coding.name())
+}
+
+pub struct ResponseCompressor {
+    wrapped: Box<dyn Handler + Send + Sync>,
+}
+
+impl ResponseCompressor {
+    pub fn new<H>(wrapped: H) -> Self
+    where
+        H: Handler + Sync + Send + 'static,
+    {
+        Self {
+            wrapped: Box::new(wrapped),
+        }
+    }
+}
+
+impl Handler for ResponseCompressor {
+    fn handle(&self, request: &HttpRequest, response: &mut HttpResponse) {
+        self.wrapped.handle(request, response);
+
+        // Partial responses describe byte offsets of the identity representation.
+        if !matches!(response.status, StatusType::Ok)
+            || response.header("Content-Encoding").is_some()
+            || !response.header("Content-Type").is_some_and(is_compressible)
+        {
+            return;
+        }
+
+        response.add_vary("Accept-Encoding");
+
+        if response.body.len() < MIN_COMPRESS_SIZE {
+            return;
+        }
+
+        let coding = match request
+            .header("Accept-Encoding")
+            .and_then(|value| negotiate(value, &ContentCoding::ALL))
+        {
+            Some(coding) => coding,
+            None => return,
+        };
+
+        let encoded = coding.encode(&response.body);
+        if encoded.len() >= response.body.len() {
+            return;
+        }
+
+        if let Some(etag) = response
+            .header("ETag")
+            .map(|etag| encoded_etag(etag, coding))
+        {
+            response.set_header("ETag", &etag);
+        }
+        response.set_header("Content-Encoding", coding.name());
+        response.set_body(encoded);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::http_header::HttpHeader;
+    use std::io::Read;
+
+    const ETAG: &str = "\"abc\"";
+
+    // Answers every request with the same response.
+    struct FixedHandler {
+        status: StatusType,
+        headers: Vec<(&'static str, &'static str)>,
+    }
+
+    impl Handler for FixedHandler {
+        fn handle(&self, _request: &HttpRequest, response: &mut HttpResponse) {
+            let headers = self
+                .headers
+                .iter()
+                .map(|(name, value)| HttpHeader::new(name, value))
+                .collect();
+            *response = HttpResponse::new(self.status, headers, "chat ".repeat(200).into_bytes());
+        }
+    }
+
+    fn compress(
+        status: StatusType,
+        headers: Vec<(&'static str, &'static str)>,
+        accept_encoding: &str,
+    ) -> HttpResponse {
+        let compressor = ResponseCompressor::new(FixedHandler { status, headers });
+        let mut request = HttpRequest::default();
+        request.headers = vec![HttpHeader::new("Accept-Encoding", accept_encoding)];
+        let mut response = HttpResponse::default();
+        compressor.handle(&request, &mut response);
+        response
+    }
+
+    fn text_headers() -> Vec<(&'static str, &'static str)> {
+        vec![("Content-Type", "text/plain"), ("ETag", ETAG)]
+    }
+
+    #[test]
+    fn test_compressor_encodes_ok_responses() {
+        let response = compress(StatusType::Ok, text_headers(), "gzip");
+
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(
+            response.header("Content-Length"),
+            Some(response.body.len().to_string().as_str())
+        );
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(&response.body[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "chat ".repeat(200).into_bytes());
+    }
+
+    #[test]
+    fn test_compressor_only_encodes_ok_responses() {
+        for status in [StatusType::NotFound, StatusType::PartialContent] {
+            let response = compress(status, text_headers(), "gzip");
+
+            assert_eq!(response.header("Content-Encoding"), None);
+            assert_eq!(response.header("ETag"), Some(ETAG));
+            assert_eq!(response.body, "chat ".repeat(200).into_bytes());
+        }
+    }
+
+    #[test]
+    fn test_compressor_skips_encoded_bodies() {
+        let mut headers = text_headers();
+        headers.push(("Content-Encoding", "br"));
+
+        let response = compress(StatusType::Ok, headers, "gzip");
+
+        assert_eq!(response.header("Content-Encoding"), Some("br"));
+        assert_eq!(response.body, "chat ".repeat(200).into_bytes());
+    }
+
+    #[test]
+    fn test_compressor_etag_per_encoding() {
+        for (accept_encoding, etag) in [
+            ("br", "\"abc-br\""),
+            ("gzip", "\"abc-gzip\""),
+            ("deflate", "\"abc-deflate\""),
+        ] {
+            let response = compress(StatusType::Ok, text_headers(), accept_encoding);
+
+            assert_eq!(response.header("Content-Encoding"), Some(accept_encoding));
+            assert_eq!(response.header("ETag"), Some(etag));
+        }
+    }
+
+    #[test]
+    fn test_compressor_respects_rejected_and_identity() {
+        for accept_encoding in ["gzip;q=0", "identity", "identity, br;q=0, *;q=0"] {
+            let response = compress(StatusType::Ok, text_headers(), accept_encoding);
+
+            assert_eq!(response.header("Content-Encoding"), None);
+            assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+            assert_eq!(response.header("ETag"), Some(ETAG));
+        }
+    }
+
+    #[test]
+    fn test_compressor_skips_binary_types() {
+        let response = compress(StatusType::Ok, vec![("Content-Type", "image/png")], "gzip");
+
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.header("Vary"), None);
+    }
+
+    #[test]
+    fn test_negotiate_prefers_highest_quality() {
+        assert_eq!(
+            negotiate("gzip;q=0.8, br;q=0.5, deflate", &ContentCoding::ALL),
+            Some(ContentCoding::Deflate)
+        );
+        assert_eq!(
+            negotiate("gzip, deflate, br", &ContentCoding::ALL),
+            Some(ContentCoding::Brotli)
+        );
+    }
+
+    #[test]
+    fn test_negotiate_respects_rejected_and_wildcard() {
+        assert_eq!(
+            negotiate("br;q=0, *;q=0.3", &ContentCoding::ALL),
+            Some(ContentCoding::Gzip)
+        );
+        assert_eq!(negotiate("identity", &ContentCoding::ALL), None);
+        assert_eq!(negotiate("br", &[ContentCoding::Gzip]), None);
+    }
+
+    #[test]
+    fn test_is_compressible() {
+        assert!(is_compressible("text/html; charset=utf-8"));
+        assert!(is_compressible("application/json"));
+        assert!(!is_compressible("image/png"));
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let data = "chat ".repeat(200).into_bytes();
+
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(&ContentCoding::Gzip.encode(&data)[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+
+        let mut decoded = Vec::new();
+        flate2::read::ZlibDecoder::new(&ContentCoding::Deflate.encode(&data)[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+
+        let mut decoded = Vec::new();
+        brotli::Decompressor::new(&ContentCoding::Brotli.encode(&data)[..], 4096)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    pub max_connections: usize,
+    pub max_connections_per_ip: usize,
+    pub max_websockets_per_ip: usize,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: 1024,
+            max_connections_per_ip: 32,
+            max_websockets_per_ip: 8,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LimitExceeded {
+    Connections,
+    ConnectionsPerIp,
+    WebsocketsPerIp,
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Connections => write!(f, "Too many connections"),
+            Self::ConnectionsPerIp => write!(f, "Too many connections from your address"),
+            Self::WebsocketsPerIp => write!(f, "Too many chat sessions from your address"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counts {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+    websockets_per_ip: HashMap<IpAddr, usize>,
+}
+
+// Counts open connections, each accepted one holds a `ConnectionGuard` until it closes.
+pub struct ConnectionTracker {
+    limits: ConnectionLimits,
+    counts: Mutex<Counts>,
+}
+
+impl ConnectionTracker {
+    pub fn new(limits: ConnectionLimits) -> Self {
+        Self {
+            limits,
+            counts: Mutex::new(Counts::default()),
+        }
+    }
+
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr) -> Result<ConnectionGuard, LimitExceeded> {
+        let mut counts = self.counts.lock().unwrap();
+        if counts.total >= self.limits.max_connections {
+            return Err(LimitExceeded::Connections);
+        }
+        let per_ip = counts.per_ip.entry(ip).or_default();
+        if *per_ip >= self.limits.max_connections_per_ip {
+            return Err(LimitExceeded::ConnectionsPerIp);
+        }
+
+        *per_ip += 1;
+        counts.total += 1;
+        Ok(ConnectionGuard {
+            tracker: Arc::clone(self),
+            ip,
+            websocket: false,
+        })
+    }
+}
+
+pub struct ConnectionGuard {
+    tracker: Arc<ConnectionTracker>,
+    ip: IpAddr,
+    websocket: bool,
+}
+
+impl ConnectionGuard {
+    // Counts the connection as websocket session from now on.
+    pub fn upgrade(&mut self) -> Result<(), LimitExceeded> {
+        if self.websocket {
+            return Ok(());
+        }
+
+        let mut counts = self.tracker.counts.lock().unwrap();
+        let websockets = counts.websockets_per_ip.entry(self.ip).or_default();
+        if *websockets >= self.tracker.limits.max_websockets_per_ip {
+            return Err(LimitExceeded::WebsocketsPerIp);
+        }
+
+        *websockets += 1;
+        self.websocket = true;
+        Ok(())
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut counts = self.tracker.counts.lock().unwrap();
+        counts.total -= 1;
+        release(&mut counts.per_ip, self.ip);
+        if self.websocket {
+            release(&mut counts.websockets_per_ip, self.ip);
+        }
+    }
+}
+
+fn release(counts: &mut HashMap<IpAddr, usize>, ip: IpAddr) {
+    if let Some(count) = counts.get_mut(&ip) {
+        *count -= 1;
+        if *count == 0 {
+            counts.remove(&ip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIRST: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 1));
+    const SECOND: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 2));
+
+    fn tracker() -> Arc<ConnectionTracker> {
+        Arc::new(ConnectionTracker::new(ConnectionLimits {
+            max_connections: 3,
+            max_connections_per_ip: 2,
+            max_websockets_per_ip: 1,
+        }))
+    }
+
+    #[test]
+    fn test_connection_limits() {
+        let tracker = tracker();
+        let first = tracker.acquire(FIRST).unwrap();
+        let _second = tracker.acquire(FIRST).unwrap();
+
+        assert_eq!(
+            tracker.acquire(FIRST).err(),
+            Some(LimitExceeded::ConnectionsPerIp)
+        );
+        let _third = tracker.acquire(SECOND).unwrap();
+        assert_eq!(
+            tracker.acquire(SECOND).err(),
+            Some(LimitExceeded::Connections)
+        );
+
+        drop(first);
+        assert!(tracker.acquire(FIRST).is_ok());
+    }
+
+    #[test]
+    fn test_websocket_limit() {
+        let tracker = tracker();
+        let mut first = tracker.acquire(FIRST).unwrap();
+        let mut second = tracker.acquire(FIRST).unwrap();
+
+        assert_eq!(first.upgrade(), Ok(()));
+        assert_eq!(second.upgrade(), Err(LimitExceeded::WebsocketsPerIp));
+
+        drop(first);
+        assert_eq!(second.upgrade(), Ok(()));
+        assert!(tracker.counts.lock().unwrap().websockets_per_ip.len() == 1);
+    }
+}
@@ -1,14 +1,43 @@
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::fs::{read, read_dir};
+use std::hash::{Hash, Hasher};
 use std::path::Path;
 
+use crate::ws::compression::{is_compressible, ContentCoding};
+use crate::ws::mime::MimeRegistry;
+
+#[derive(Clone)]
+struct StoredFile {
+    content: Vec<u8>,
+    etag: String,
+    content_type: String,
+    encoded: HashMap<ContentCoding, Vec<u8>>,
+}
+
+impl StoredFile {
+    fn new(content: Vec<u8>, content_type: String) -> Self {
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        let etag = format!("\"{:x}-{:x}\"", content.len(), hasher.finish());
+        Self {
+            content,
+            etag,
+            content_type,
+            encoded: HashMap::new(),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct FileStorage {
-    files: HashMap<String, Vec<u8>>,
+    files: HashMap<String, StoredFile>,
+    mime_types: MimeRegistry,
 }
 
 impl FileStorage {
     pub fn new(path: &Path) -> Option<Self> {
+        let mime_types = MimeRegistry::new();
         let mut files = HashMap::new();
         let entries = match read_dir(path) {
             Ok(entries) => entries,
@@ -39,17 +68,74 @@ impl FileStorage {
                 }
             };
 
-            files.insert(file_entry.file_name().into_string().unwrap(), file_content);
+            let file_name = file_entry.file_name().into_string().unwrap();
+            let content_type = mime_types.content_type(&file_name, &file_content);
+            files.insert(file_name, StoredFile::new(file_content, content_type));
             println!(
                 "Successfully loaded file: {}.",
                 file_entry.file_name().to_str().unwrap()
             );
         }
 
-        Some(Self { files })
+        Some(Self { files, mime_types })
     }
 
     pub fn get(&self, file: &str) -> Option<&Vec<u8>> {
-        self.files.get(file)
+        self.files.get(file).map(|stored| &stored.content)
+    }
+
+    pub fn etag(&self, file: &str) -> Option<&str> {
+        self.files.get(file).map(|stored| stored.etag.as_str())
+    }
+
+    // Content types are resolved once per file, so files loaded before this call are
+    // updated here instead of on every request.
+    pub fn register_mime_type(&mut self, extension: &str, mime: &str) -> &mut Self {
+        self.mime_types.register(extension, mime);
+        for (name, stored) in self.files.iter_mut() {
+            stored.content_type = self.mime_types.content_type(name, &stored.content);
+        }
+        self
+    }
+
+    pub fn content_type(&self, file: &str) -> Option<&str> {
+        self.files
+            .get(file)
+            .map(|stored| stored.content_type.as_str())
+    }
+
+    // Encodes every file once up front, so static responses don't have to be compressed
+    // per request. Encodings that don't make the file smaller are dropped.
+    pub fn precompress(&mut self) {
+        for (name, stored) in self.files.iter_mut() {
+            if !is_compressible(&stored.content_type) {
+                continue;
+            }
+
+            for coding in ContentCoding::ALL {
+                let encoded = coding.encode(&stored.content);
+                if encoded.len() < stored.content.len() {
+                    stored.encoded.insert(coding, encoded);
+                }
+            }
+            println!(
+                "Precompressed file: {} ({} encodings).",
+                name,
+                stored.encoded.len()
+            );
+        }
+    }
+
+    pub fn get_encoded(&self, file: &str, coding: ContentCoding) -> Option<&Vec<u8>> {
+        self.files
+            .get(file)
+            .and_then(|stored| stored.encoded.get(&coding))
+    }
+
+    pub fn encodings(&self, file: &str) -> Vec<ContentCoding> {
+        self.files
+            .get(file)
+            .map(|stored| stored.encoded.keys().copied().collect())
+            .unwrap_or_default()
     }
 }
@@ -0,0 +1,78 @@
+#[derive(Clone, Copy, Debug)]
+pub enum SameSite {
+    Strict,
+}
+
+// Value of a Set-Cookie header.
+#[derive(Clone, Debug)]
+pub struct SetCookie {
+    pub name: String,
+    pub value: String,
+    pub path: String,
+    pub max_age: Option<u64>,
+    pub http_only: bool,
+    pub secure: bool,
+    pub same_site: Option<SameSite>,
+}
+
+impl SetCookie {
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: String::from("/"),
+            max_age: None,
+            http_only: true,
+            secure: false,
+            same_site: Some(SameSite::Strict),
+        }
+    }
+
+    // Cookie that makes the browser drop a previously set one of the same name.
+    pub fn expired(name: &str) -> Self {
+        Self {
+            max_age: Some(0),
+            ..Self::new(name, "")
+        }
+    }
+
+    pub fn header_value(&self) -> String {
+        let mut value = format!("{}={}; Path={}", self.name, self.value, self.path);
+        if let Some(max_age) = self.max_age {
+            value.push_str(&format!("; Max-Age={}", max_age));
+        }
+        if self.http_only {
+            value.push_str("; HttpOnly");
+        }
+        if self.secure {
+            value.push_str("; Secure");
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => value.push_str("; SameSite=Strict"),
+            None => {}
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_cookie_header_value() {
+        let cookie = SetCookie {
+            max_age: Some(3600),
+            ..SetCookie::new("chat_session", "abc")
+        };
+
+        assert_eq!(
+            cookie.header_value(),
+            "chat_session=abc; Path=/; Max-Age=3600; HttpOnly; SameSite=Strict"
+        );
+        assert_eq!(
+            SetCookie::expired("chat_session").header_value(),
+            "chat_session=; Path=/; Max-Age=0; HttpOnly; SameSite=Strict"
+        );
+    }
+}
@@ -0,0 +1,255 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl ByteRange {
+    pub fn content_range(&self, total: usize) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeError {
+    // Header is syntactically invalid and should be ignored.
+    Malformed,
+    // Header is valid, but none of the ranges overlaps the representation.
+    Unsatisfiable,
+}
+
+// Ranges accepted in one header, more are treated as a malformed header.
+pub const MAX_RANGES: usize = 16;
+
+pub fn parse_range_header(value: &str, total: usize) -> Result<Vec<ByteRange>, RangeError> {
+    let (unit, specs) = value.trim().split_once('=').ok_or(RangeError::Malformed)?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return Err(RangeError::Malformed);
+    }
+
+    let specs: Vec<&str> = specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return Err(RangeError::Malformed);
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs {
+        let (first, last) = spec.split_once('-').ok_or(RangeError::Malformed)?;
+        let (first, last) = (first.trim(), last.trim());
+
+        if first.is_empty() {
+            // Suffix range: last N bytes of the representation.
+            let suffix = parse_position(last)?;
+            if suffix == 0 || total == 0 {
+                continue;
+            }
+            ranges.push(ByteRange {
+                start: total - suffix.min(total),
+                end: total - 1,
+            });
+            continue;
+        }
+
+        let start = parse_position(first)?;
+        let end = if last.is_empty() {
+            None
+        } else {
+            Some(parse_position(last)?)
+        };
+
+        if let Some(end) = end {
+            if end < start {
+                return Err(RangeError::Malformed);
+            }
+        }
+
+        if start >= total {
+            continue;
+        }
+
+        ranges.push(ByteRange {
+            start,
+            end: end.map_or(total - 1, |end| end.min(total - 1)),
+        });
+    }
+
+    if ranges.is_empty() {
+        return Err(RangeError::Unsatisfiable);
+    }
+
+    // Overlapping and adjacent ranges are sent as one, so a client can't ask for the
+    // same bytes many times over.
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end + 1 => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    Ok(merged)
+}
+
+pub fn multipart_byteranges(
+    content: &[u8],
+    ranges: &[ByteRange],
+    content_type: &str,
+    boundary: &str,
+) -> Vec<u8> {
+    let mut body = Vec::new();
+
+    for range in ranges {
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                boundary,
+                content_type,
+                range.content_range(content.len())
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&content[range.start..=range.end]);
+        body.extend_from_slice("\r\n".as_bytes());
+    }
+
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    body
+}
+
+fn parse_position(value: &str) -> Result<usize, RangeError> {
+    if value.is_empty() || !value.chars().all(|c| c.is_ascii_digit()) {
+        return Err(RangeError::Malformed);
+    }
+    value.parse::<usize>().map_err(|_| RangeError::Malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_single_range() {
+        let ranges = parse_range_header("bytes=0-9", 100).unwrap();
+
+        assert_eq!(ranges, vec![ByteRange { start: 0, end: 9 }]);
+    }
+
+    #[test]
+    fn test_parse_open_and_suffix_ranges() {
+        let ranges = parse_range_header("bytes=90-, -5", 100).unwrap();
+        assert_eq!(ranges, vec![ByteRange { start: 90, end: 99 }]);
+
+        let ranges = parse_range_header("bytes=-5, 0-", 100).unwrap();
+        assert_eq!(ranges, vec![ByteRange { start: 0, end: 99 }]);
+
+        let ranges = parse_range_header("bytes=-5, 10-19", 100).unwrap();
+        assert_eq!(
+            ranges,
+            vec![
+                ByteRange { start: 10, end: 19 },
+                ByteRange { start: 95, end: 99 }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_overlapping_ranges_are_merged() {
+        let ranges = parse_range_header("bytes=0-,0-,0-", 100).unwrap();
+        assert_eq!(ranges, vec![ByteRange { start: 0, end: 99 }]);
+
+        let ranges = parse_range_header("bytes=20-29, 0-9, 10-14, 25-39, 50-59", 100).unwrap();
+        assert_eq!(
+            ranges,
+            vec![
+                ByteRange { start: 0, end: 14 },
+                ByteRange { start: 20, end: 39 },
+                ByteRange { start: 50, end: 59 }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_range_end_is_clamped() {
+        let ranges = parse_range_header("bytes=50-1000", 100).unwrap();
+
+        assert_eq!(ranges, vec![ByteRange { start: 50, end: 99 }]);
+        assert_eq!(ranges[0].content_range(100), "bytes 50-99/100");
+    }
+
+    #[test]
+    fn test_parse_unsatisfiable_range() {
+        assert_eq!(
+            parse_range_header("bytes=100-200", 100),
+            Err(RangeError::Unsatisfiable)
+        );
+        assert_eq!(
+            parse_range_header("bytes=-0", 100),
+            Err(RangeError::Unsatisfiable)
+        );
+    }
+
+    #[test]
+    fn test_parse_malformed_range() {
+        assert_eq!(
+            parse_range_header("items=0-9", 100),
+            Err(RangeError::Malformed)
+        );
+        assert_eq!(
+            parse_range_header("bytes=9-0", 100),
+            Err(RangeError::Malformed)
+        );
+        assert_eq!(
+            parse_range_header("bytes=a-b", 100),
+            Err(RangeError::Malformed)
+        );
+        assert_eq!(
+            parse_range_header("bytes=", 100),
+            Err(RangeError::Malformed)
+        );
+        assert_eq!(
+            parse_range_header("bytes= , ", 100),
+            Err(RangeError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_parse_too_many_ranges() {
+        let specs: Vec<String> = (0..=MAX_RANGES).map(|i| format!("{}-{}", i, i)).collect();
+        assert_eq!(
+            parse_range_header(&format!("bytes={}", specs.join(",")), 100),
+            Err(RangeError::Malformed)
+        );
+        let specs: Vec<String> = (0..MAX_RANGES)
+            .map(|i| format!("{}-{}", i * 2, i * 2))
+            .collect();
+        assert_eq!(
+            parse_range_header(&format!("bytes={}", specs.join(",")), 100)
+                .unwrap()
+                .len(),
+            MAX_RANGES
+        );
+    }
+
+    #[test]
+    fn test_multipart_byteranges() {
+        let content = b"0123456789";
+        let ranges = vec![
+            ByteRange { start: 0, end: 1 },
+            ByteRange { start: 8, end: 9 },
+        ];
+
+        let body = multipart_byteranges(content, &ranges, "text/plain", "sep");
+
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            "--sep\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\r\n\
+             --sep\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n89\r\n\
+             --sep--\r\n"
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use std::net::IpAddr;
+
 use crate::ws::http_header::HttpHeader;
 
 #[derive(Clone, Debug)]
@@ -7,7 +9,9 @@ pub struct HttpRequest {
     pub headers: Vec<HttpHeader>,
     pub version_major: u8,
     pub version_minor: u8,
-    pub _body: String,
+    pub body: String,
+    // Address of the client, None when the request did not come from a socket.
+    pub remote_ip: Option<IpAddr>,
 }
 
 impl HttpRequest {
@@ -18,7 +22,118 @@ impl HttpRequest {
             headers: Vec::new(),
             version_major: 0,
             version_minor: 0,
-            _body: String::new(),
+            body: String::new(),
+            remote_ip: None,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        match self.uri.split_once('?') {
+            Some((path, _)) => path,
+            None => &self.uri,
+        }
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        self.uri.split_once('?').map(|(_, query)| query)
+    }
+
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query()?
+            .split('&')
+            .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .filter(|header| header.name.eq_ignore_ascii_case("Cookie"))
+            .flat_map(|header| header.value.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.trim_matches('"'))
+    }
+
+    // Field of an application/x-www-form-urlencoded body.
+    pub fn form_param(&self, name: &str) -> Option<String> {
+        self.body
+            .split('&')
+            .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+            .find(|(key, _)| url_decode(key) == name)
+            .map(|(_, value)| url_decode(value))
+    }
+
+    pub fn content_length(&self) -> Option<usize> {
+        self.header("Content-Length")?.trim().parse().ok()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value.as_str())
+    }
+}
+
+fn url_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2]));
+                if let (Some(high), Some(low)) = hex {
+                    decoded.push(high << 4 | low);
+                    i += 2;
+                } else {
+                    decoded.push(b'%');
+                }
+            }
+            byte => decoded.push(byte),
         }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|digit| digit as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_form_param() {
+        let mut request = HttpRequest::default();
+        request.body = String::from("nick=alice&password=p%40ss+word%21&empty");
+
+        assert_eq!(request.form_param("nick").as_deref(), Some("alice"));
+        assert_eq!(
+            request.form_param("password").as_deref(),
+            Some("p@ss word!")
+        );
+        assert_eq!(request.form_param("empty").as_deref(), Some(""));
+        assert_eq!(request.form_param("missing"), None);
+    }
+
+    #[test]
+    fn test_path_query_and_cookie() {
+        let mut request = HttpRequest::default();
+        request.uri = String::from("/chat?room=main&flag");
+        request.headers = vec![HttpHeader::new("cookie", "a=1; chat_session=xyz")];
+
+        assert_eq!(request.path(), "/chat");
+        assert_eq!(request.query_param("room"), Some("main"));
+        assert_eq!(request.query_param("flag"), Some(""));
+        assert_eq!(request.cookie("chat_session"), Some("xyz"));
+        assert_eq!(request.cookie("missing"), None);
     }
 }
@@ -31,11 +31,27 @@ enum ParserState {
 }
 
 fn is_tspecial(c: char) -> bool {
-    match c {
-        '(' | ')' | '<' | '>' | '@' | ',' | ';' | ':' | '\\' | '"' | '/' | '[' | ']' | '?'
-        | '=' | '{' | '}' | ' ' | '\t' => true,
-        _ => false,
-    }
+    matches!(
+        c,
+        '(' | ')'
+            | '<'
+            | '>'
+            | '@'
+            | ','
+            | ';'
+            | ':'
+            | '\\'
+            | '"'
+            | '/'
+            | '['
+            | ']'
+            | '?'
+            | '='
+            | '{'
+            | '}'
+            | ' '
+            | '\t'
+    )
 }
 
 #[derive(Clone)]
@@ -59,7 +75,7 @@ impl HttpRequestParser {
 
                 request.method.push(c);
                 self.state = ParserState::Method;
-                return ParseResult::Indeterminate;
+                ParseResult::Indeterminate
             }
             ParserState::Method => {
                 if c == ' ' {
@@ -73,7 +89,7 @@ impl HttpRequestParser {
 
                 request.method.push(c);
                 self.state = ParserState::Method;
-                return ParseResult::Indeterminate;
+                ParseResult::Indeterminate
             }
             ParserState::Uri => {
                 if c == ' ' {
@@ -86,7 +102,7 @@ impl HttpRequestParser {
                 }
 
                 request.uri.push(c);
-                return ParseResult::Indeterminate;
+                ParseResult::Indeterminate
             }
             ParserState::HttpVersionH => {
                 if c == 'H' {
@@ -94,7 +110,7 @@ impl HttpRequestParser {
                     return ParseResult::Indeterminate;
                 }
 
-                return ParseResult::Bad;
+                ParseResult::Bad
             }
             ParserState::HttpVersionT1 => {
                 if c == 'T' {
@@ -102,7 +118,7 @@ impl HttpRequestParser {
                     return ParseResult::Indeterminate;
                 }
 
-                return ParseResult::Bad;
+                ParseResult::Bad
             }
             ParserState::HttpVersionT2 => {
                 if c == 'T' {
@@ -110,7 +126,7 @@ impl HttpRequestParser {
                     return ParseResult::Indeterminate;
                 }
 
-                return ParseResult::Bad;
+                ParseResult::Bad
             }
             ParserState::HttpVersionP => {
                 if c == 'P' {
@@ -118,7 +134,7 @@ impl HttpRequestParser {
                     return ParseResult::Indeterminate;
                 }
 
-                return ParseResult::Bad;
+                ParseResult::Bad
             }
             ParserState::HttpVersionSlash => {
                 if c == '/' {
@@ -128,7 +144,7 @@ impl HttpRequestParser {
                     return ParseResult::Indeterminate;
                 }
 
-                return ParseResult::Bad;
+                ParseResult::Bad
             }
             ParserState::HttpVersionMajorStart => {
                 if !c.is_ascii_digit() {
@@ -137,7 +153,7 @@ impl HttpRequestParser {
 
                 request.version_major = c.to_digit(10).unwrap() as u8;
                 self.state = ParserState::HttpVersionMajor;
-                return ParseResult::Indeterminate;
+                ParseResult::Indeterminate
             }
             ParserState::HttpVersionMajor => {
                 if c == '.' {
@@ -150,7 +166,7 @@ impl HttpRequestParser {
                 }
 
                 request.version_major = request.version_major * 10 + c.to_digit(10).unwrap() as u8;
-                return ParseResult::Indeterminate;
+                ParseResult::Indeterminate
             }
             ParserState::HttpVersionMinorStart => {
                 if !c.is_ascii_digit() {
@@ -159,7 +175,7 @@ impl HttpRequestParser {
 
                 request.version_minor = c.to_digit(10).unwrap() as u8;
                 self.state = ParserState::HttpVersionMinor;
-                return ParseResult::Indeterminate;
+                ParseResult::Indeterminate
             }
             ParserState::HttpVersionMinor => {
                 if c == '\r' {
@@ -172,7 +188,7 @@ impl HttpRequestParser {
                 }
 
                 request.version_minor = request.version_minor * 10 + c.to_digit(10).unwrap() as u8;
-                return ParseResult::Indeterminate;
+                ParseResult::Indeterminate
             }
             ParserState::NewLine1 => {
                 if c != '\n' {
@@ -180,7 +196,7 @@ impl HttpRequestParser {
                 }
 
                 self.state = ParserState::HeaderLineStart;
-                return ParseResult::Indeterminate;
+                ParseResult::Indeterminate
             }
             ParserState::HeaderLineStart => {
                 if c == '\r' {
@@ -195,7 +211,7 @@ impl HttpRequestParser {
                 request.headers.push(HttpHeader::default());
                 request.headers.last_mut().unwrap().name.push(c);
                 self.state = ParserState::HeaderName;
-                return ParseResult::Indeterminate;
+                ParseResult::Indeterminate
             }
             ParserState::HeaderName => {
                 if c == ':' {
@@ -208,7 +224,7 @@ impl HttpRequestParser {
                 }
 
                 request.headers.last_mut().unwrap().name.push(c);
-                return ParseResult::Indeterminate;
+                ParseResult::Indeterminate
             }
             ParserState::SpaceBeforeHeaderValue => {
                 if c == ' ' {
@@ -216,7 +232,7 @@ impl HttpRequestParser {
                     return ParseResult::Indeterminate;
                 }
 
-                return ParseResult::Indeterminate;
+                ParseResult::Indeterminate
             }
             ParserState::HeaderValue => {
                 if c == '\r' {
@@ -229,7 +245,7 @@ impl HttpRequestParser {
                 }
 
                 request.headers.last_mut().unwrap().value.push(c);
-                return ParseResult::Indeterminate;
+                ParseResult::Indeterminate
             }
             ParserState::NewLine2 => {
                 if c == '\n' {
@@ -237,13 +253,13 @@ impl HttpRequestParser {
                     return ParseResult::Indeterminate;
                 }
 
-                return ParseResult::Bad;
+                ParseResult::Bad
             }
             ParserState::NewLine3 => {
                 if c == '\n' {
                     return ParseResult::Ok;
                 }
-                return ParseResult::Bad;
+                ParseResult::Bad
             }
         }
     }
@@ -1,34 +1,68 @@
+use std::fmt;
+
+use crate::ws::http_cookie::SetCookie;
 use crate::ws::http_header::HttpHeader;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub enum StatusType {
+    SwitchingProtocols = 101,
     Ok = 200,
     // Created = 201,
     // Accepted = 202,
     // NoContent = 204,
+    PartialContent = 206,
     // MultipleChoices = 300,
     // MovedPermanently = 301,
     // MovedTemporarily = 302,
+    SeeOther = 303,
     // NotModified = 304,
-    // BadRequest = 400,
-    // Unauthorized = 401,
-    // Forbidden = 403,
+    BadRequest = 400,
+    Unauthorized = 401,
+    Forbidden = 403,
     NotFound = 404,
     MethodNotAllowed = 405,
+    PayloadTooLarge = 413,
+    RangeNotSatisfiable = 416,
+    UpgradeRequired = 426,
+    TooManyRequests = 429,
+    RequestHeaderFieldsTooLarge = 431,
     InternalServerError = 500,
     // NotImplemented = 501,
     // BadGetway = 502,
-    // ServiceUnavailable = 503,
+    ServiceUnavailable = 503,
 }
 
 impl StatusType {
-    fn to_string(&self) -> String {
-        match self {
-            Self::Ok => String::from("Ok"),
-            Self::NotFound => String::from("Not Found"),
-            Self::MethodNotAllowed => String::from("Method Not Allowed"),
-            Self::InternalServerError => String::from("Internal Server Errror"),
-        }
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+
+    pub fn is_error(self) -> bool {
+        self.code() >= 400
+    }
+}
+
+impl fmt::Display for StatusType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            Self::SwitchingProtocols => "Switching Protocols",
+            Self::Ok => "Ok",
+            Self::PartialContent => "Partial Content",
+            Self::SeeOther => "See Other",
+            Self::BadRequest => "Bad Request",
+            Self::Unauthorized => "Unauthorized",
+            Self::Forbidden => "Forbidden",
+            Self::NotFound => "Not Found",
+            Self::MethodNotAllowed => "Method Not Allowed",
+            Self::PayloadTooLarge => "Payload Too Large",
+            Self::RangeNotSatisfiable => "Range Not Satisfiable",
+            Self::UpgradeRequired => "Upgrade Required",
+            Self::TooManyRequests => "Too Many Requests",
+            Self::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            Self::InternalServerError => "Internal Server Error",
+            Self::ServiceUnavailable => "Service Unavailable",
+        };
+        write!(f, "{}", reason)
     }
 }
 
@@ -57,16 +91,54 @@ impl HttpResponse {
         }
     }
 
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value.as_str())
+    }
+
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        match self
+            .headers
+            .iter_mut()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+        {
+            Some(header) => header.value = value.to_string(),
+            None => self.headers.push(HttpHeader::new(name, value)),
+        }
+    }
+
+    pub fn add_cookie(&mut self, cookie: &SetCookie) {
+        self.headers
+            .push(HttpHeader::new("Set-Cookie", &cookie.header_value()));
+    }
+
+    pub fn add_vary(&mut self, field: &str) {
+        let vary = match self.header("Vary") {
+            Some(vary)
+                if vary
+                    .split(',')
+                    .any(|value| value.trim().eq_ignore_ascii_case(field)) =>
+            {
+                return;
+            }
+            Some(vary) => format!("{}, {}", vary, field),
+            None => field.to_string(),
+        };
+        self.set_header("Vary", &vary);
+    }
+
+    pub fn set_body(&mut self, body: Vec<u8>) {
+        self.set_header("Content-Length", &body.len().to_string());
+        self.body = body;
+    }
+
     pub fn bytes(&self) -> Vec<u8> {
         let mut response = Vec::new();
 
         response.extend_from_slice(
-            format!(
-                "HTTP/1.1 {} {}\r\n",
-                self.status as u16,
-                self.status.to_string()
-            )
-            .as_bytes(),
+            format!("HTTP/1.1 {} {}\r\n", self.status.code(), self.status).as_bytes(),
         );
 
         for header in &self.headers {
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::str::FromStr;
 use std::sync::Arc;
 
@@ -8,27 +9,64 @@ use crate::ws::http_header::HttpHeader;
 use crate::ws::http_request::HttpRequest;
 use crate::ws::http_response::{HttpResponse, StatusType};
 use crate::ws::method::Method;
+use crate::ws::session_store::{SessionStore, SESSION_COOKIE};
+use crate::ws::token_auth::{find_token, TokenValidator, TOKEN_PROTOCOL_PREFIX};
+use crate::ws::ws_handler::{WsContext, WsHandler, WsUpgrade};
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+
+// Statuses rendered from "<code>.html" in the doc root, or a built-in page if it is missing.
+const DEFAULT_ERROR_PAGES: [StatusType; 3] = [
+    StatusType::NotFound,
+    StatusType::MethodNotAllowed,
+    StatusType::InternalServerError,
+];
 
 macro_rules! check_or_handle_error {
-    ($expr:expr, $error_page:expr, $status:expr, $request:expr, $response:expr, $self:expr) => {
+    ($expr:expr, $status:expr, $request:expr, $response:expr, $self:expr) => {
+        check_or_handle_error!($expr, $status, $request, $response, $self, ())
+    };
+    ($expr:expr, $status:expr, $request:expr, $response:expr, $self:expr, $ret:expr) => {
         if let Some(value) = $expr {
             value
         } else {
-            $self.handle_error($error_page.to_string(), $status, &$request, $response);
-            return;
+            $self.handle_error($status, &$request, $response);
+            return $ret;
         }
     };
 }
 
 pub struct HttpRouter {
     routes: HashMap<Method, HashMap<String, Box<dyn Handler + Sync + Send>>>,
+    error_handlers: HashMap<StatusType, Box<dyn Handler + Sync + Send>>,
+    ws_routes: HashMap<String, Arc<dyn WsHandler + Sync + Send>>,
+    // None accepts upgrades from any origin.
+    allowed_origins: Option<Vec<String>>,
+    token_validator: Option<TokenValidator>,
+    sessions: Option<Arc<SessionStore>>,
+    auth_required: bool,
     file_storage: Arc<FileStorage>,
 }
 
 impl HttpRouter {
     pub fn new(file_storage: Arc<FileStorage>) -> Self {
+        for status in DEFAULT_ERROR_PAGES {
+            let error_file = error_page_name(status);
+            if file_storage.get(&error_file).is_none() {
+                eprintln!(
+                    "Error page {} is missing, built-in default will be used.",
+                    error_file
+                );
+            }
+        }
+
         Self {
             routes: HashMap::new(),
+            error_handlers: HashMap::new(),
+            ws_routes: HashMap::new(),
+            allowed_origins: None,
+            token_validator: None,
+            sessions: None,
+            auth_required: false,
             file_storage,
         }
     }
@@ -37,18 +75,12 @@ impl HttpRouter {
         let method = if let Ok(method) = Method::from_str(request.method.as_str()) {
             method
         } else {
-            self.handle_error(
-                "405.html".to_string(),
-                StatusType::MethodNotAllowed,
-                &request,
-                response,
-            );
+            self.handle_error(StatusType::MethodNotAllowed, request, response);
             return;
         };
 
         let inner_map = check_or_handle_error!(
             self.routes.get(&method),
-            "405.html",
             StatusType::MethodNotAllowed,
             &request,
             response,
@@ -56,15 +88,25 @@ impl HttpRouter {
         );
 
         let handler = check_or_handle_error!(
-            inner_map.get(&request.uri),
-            "404.html",
+            inner_map.get(request.path()),
             StatusType::NotFound,
             &request,
             response,
             self
         );
 
-        handler.handle(request, response);
+        if !self.handle_catching_panic(handler.as_ref(), request, response) {
+            self.handle_error(StatusType::InternalServerError, request, response);
+            return;
+        }
+
+        // Handlers signal errors with an empty body, the router renders the page.
+        if response.status.is_error()
+            && response.body.is_empty()
+            && self.has_error_page(response.status)
+        {
+            self.handle_error(response.status, request, response);
+        }
     }
 
     pub fn add_route<H>(&mut self, method: Method, uri: String, handler: H) -> &mut Self
@@ -73,26 +115,535 @@ impl HttpRouter {
     {
         self.routes
             .entry(method)
-            .or_insert_with(HashMap::new)
+            .or_default()
             .insert(uri, Box::new(handler));
         self
     }
 
-    fn handle_error(
+    pub fn add_ws_route<H>(&mut self, uri: String, handler: H) -> &mut Self
+    where
+        H: WsHandler + Send + Sync + 'static,
+    {
+        self.ws_routes.insert(uri, Arc::new(handler));
+        self
+    }
+
+    // Restricts websocket upgrades to pages served from `origins`, e.g. "http://localhost:6969".
+    // Requests without Origin header are not sent by browsers and are still accepted.
+    pub fn set_allowed_origins(&mut self, origins: Vec<String>) -> &mut Self {
+        self.allowed_origins = Some(origins);
+        self
+    }
+
+    // Websocket upgrades presenting an access token are accepted only if it is valid,
+    // with `required` upgrades without token or login session are rejected too.
+    pub fn set_token_auth(&mut self, validator: TokenValidator, required: bool) -> &mut Self {
+        self.token_validator = Some(validator);
+        self.auth_required = required;
+        self
+    }
+
+    // Websocket upgrades carrying the cookie of a logged in session are authenticated
+    // as the session's nickname.
+    pub fn set_session_store(&mut self, sessions: Arc<SessionStore>) -> &mut Self {
+        self.sessions = Some(sessions);
+        self
+    }
+
+    // Validates a websocket upgrade request. On success `response` holds the handshake
+    // response, otherwise it holds the error page.
+    pub fn route_websocket(
         &self,
-        error_file: String,
-        status: StatusType,
-        _: &HttpRequest,
+        request: &HttpRequest,
         response: &mut HttpResponse,
-    ) {
-        let file_content = match self.file_storage.get(&error_file) {
-            Some(content) => content,
-            None => unreachable!("404.html and 405.html should be already verified and cached."),
+    ) -> Option<WsUpgrade> {
+        if request.method != "GET" {
+            self.handle_error(StatusType::MethodNotAllowed, request, response);
+            return None;
+        }
+
+        let handler = check_or_handle_error!(
+            self.ws_routes.get(request.path()),
+            StatusType::NotFound,
+            &request,
+            response,
+            self,
+            None
+        );
+
+        if !self.is_origin_allowed(request.header("Origin")) {
+            eprintln!(
+                "Websocket upgrade from not allowed origin: {}",
+                request.header("Origin").unwrap_or_default()
+            );
+            self.handle_error(StatusType::Forbidden, request, response);
+            return None;
+        }
+
+        if request.header("Sec-WebSocket-Version").map(str::trim) != Some("13") {
+            self.handle_error(StatusType::UpgradeRequired, request, response);
+            response.set_header("Sec-WebSocket-Version", "13");
+            return None;
+        }
+
+        let key = check_or_handle_error!(
+            request.header("Sec-WebSocket-Key"),
+            StatusType::BadRequest,
+            &request,
+            response,
+            self,
+            None
+        );
+
+        let user = match self.authenticate(request) {
+            Ok(user) => user,
+            Err(_) => {
+                self.handle_error(StatusType::Unauthorized, request, response);
+                return None;
+            }
         };
 
-        let mut headers = Vec::new();
-        headers.push(HttpHeader::new("Content-Type", "text/html"));
+        let protocol = select_protocol(
+            request.header("Sec-WebSocket-Protocol"),
+            handler.protocols(),
+        );
+
+        let mut headers = vec![
+            HttpHeader::new("Upgrade", "websocket"),
+            HttpHeader::new("Connection", "Upgrade"),
+            HttpHeader::new(
+                "Sec-WebSocket-Accept",
+                &derive_accept_key(key.trim().as_bytes()),
+            ),
+        ];
+        // Browsers fail the handshake unless one of the offered protocols is echoed, so
+        // a token offered without a chat protocol is echoed and the codec is detected
+        // from the first frame.
+        let echoed = protocol
+            .as_deref()
+            .or_else(|| token_protocol(request.header("Sec-WebSocket-Protocol")));
+        if let Some(echoed) = echoed {
+            headers.push(HttpHeader::new("Sec-WebSocket-Protocol", echoed));
+        }
+
+        *response = HttpResponse {
+            status: StatusType::SwitchingProtocols,
+            headers,
+            body: Vec::new(),
+        };
+
+        Some(WsUpgrade {
+            context: WsContext {
+                request: request.clone(),
+                protocol,
+                user,
+            },
+            handler: Arc::clone(handler),
+            leftover: Vec::new(),
+        })
+    }
+
+    fn authenticate(&self, request: &HttpRequest) -> Result<Option<String>, ()> {
+        if let (Some(validator), Some(token)) = (&self.token_validator, find_token(request)) {
+            return match validator.validate(token) {
+                Ok(claims) => Ok(Some(claims.sub)),
+                Err(e) => {
+                    eprintln!("Websocket upgrade with invalid access token: {}", e);
+                    Err(())
+                }
+            };
+        }
+
+        let session_user = self.sessions.as_ref().and_then(|sessions| {
+            request
+                .cookie(SESSION_COOKIE)
+                .and_then(|id| sessions.get(id))
+        });
+        if session_user.is_some() {
+            return Ok(session_user);
+        }
+
+        if self.auth_required {
+            eprintln!("Websocket upgrade without required authentication");
+            return Err(());
+        }
+
+        Ok(None)
+    }
+
+    fn is_origin_allowed(&self, origin: Option<&str>) -> bool {
+        match (&self.allowed_origins, origin) {
+            (None, _) | (Some(_), None) => true,
+            (Some(allowed), Some(origin)) => allowed.iter().any(|allowed| {
+                allowed
+                    .trim_end_matches('/')
+                    .eq_ignore_ascii_case(origin.trim())
+            }),
+        }
+    }
+
+    // Replaces the error page for `status`. The response passed to the handler already
+    // carries the status, the handler is expected to fill headers and body.
+    #[allow(dead_code)]
+    pub fn add_error_handler<H>(&mut self, status: StatusType, handler: H) -> &mut Self
+    where
+        H: Handler + Send + Sync + 'static,
+    {
+        self.error_handlers.insert(status, Box::new(handler));
+        self
+    }
+
+    fn has_error_page(&self, status: StatusType) -> bool {
+        self.error_handlers.contains_key(&status) || DEFAULT_ERROR_PAGES.contains(&status)
+    }
+
+    fn handle_catching_panic(
+        &self,
+        handler: &(dyn Handler + Sync + Send),
+        request: &HttpRequest,
+        response: &mut HttpResponse,
+    ) -> bool {
+        match catch_unwind(AssertUnwindSafe(|| handler.handle(request, response))) {
+            Ok(_) => true,
+            Err(_) => {
+                eprintln!(
+                    "Handler for {} {} panicked, responding with 500",
+                    request.method, request.uri
+                );
+                false
+            }
+        }
+    }
+
+    fn handle_error(&self, status: StatusType, request: &HttpRequest, response: &mut HttpResponse) {
+        *response = HttpResponse::new(status, Vec::new(), Vec::new());
+
+        if let Some(handler) = self.error_handlers.get(&status) {
+            if self.handle_catching_panic(handler.as_ref(), request, response) {
+                return;
+            }
+            *response = HttpResponse::new(status, Vec::new(), Vec::new());
+        }
+
+        let file_content = match self.file_storage.get(&error_page_name(status)) {
+            Some(content) => content.to_vec(),
+            None => default_error_page(status).into_bytes(),
+        };
+
+        let headers = vec![HttpHeader::new("Content-Type", "text/html; charset=utf-8")];
+
+        *response = HttpResponse::new(status, headers, file_content);
+    }
+}
+
+fn select_protocol(offered: Option<&str>, supported: &[&'static str]) -> Option<String> {
+    offered?
+        .split(',')
+        .map(str::trim)
+        .find(|protocol| supported.contains(protocol))
+        .map(str::to_string)
+}
+
+fn token_protocol(offered: Option<&str>) -> Option<&str> {
+    offered?
+        .split(',')
+        .map(str::trim)
+        .find(|protocol| protocol.starts_with(TOKEN_PROTOCOL_PREFIX))
+}
+
+fn error_page_name(status: StatusType) -> String {
+    format!("{}.html", status.code())
+}
+
+fn default_error_page(status: StatusType) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n    <meta charset=\"UTF-8\">\n    \
+         <title>{code} {reason}</title>\n</head>\n<body>\n    <h1>{code} {reason}</h1>\n</body>\n</html>\n",
+        code = status.code(),
+        reason = status
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    struct PanickingHandler {}
+
+    impl Handler for PanickingHandler {
+        fn handle(&self, _request: &HttpRequest, _response: &mut HttpResponse) {
+            panic!("Handler failure");
+        }
+    }
+
+    struct PlainTextHandler {
+        text: &'static str,
+    }
+
+    impl Handler for PlainTextHandler {
+        fn handle(&self, _request: &HttpRequest, response: &mut HttpResponse) {
+            let headers = vec![HttpHeader::new("Content-Type", "text/plain")];
+            *response = HttpResponse::new(response.status, headers, self.text.as_bytes().to_vec());
+        }
+    }
+
+    struct NoopWsHandler {}
+
+    impl WsHandler for NoopWsHandler {
+        fn handle(
+            &self,
+            _context: WsContext,
+            _socket: crate::ws::ws_handler::WsStream,
+        ) -> futures::future::BoxFuture<'static, ()> {
+            Box::pin(async {})
+        }
+
+        fn protocols(&self) -> &[&'static str] {
+            &["chat.v1.json", "chat.v1.msgpack"]
+        }
+    }
+
+    fn router() -> HttpRouter {
+        let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+        HttpRouter::new(Arc::new(FileStorage::new(&assets).unwrap()))
+    }
+
+    fn request(method: &str, uri: &str) -> HttpRequest {
+        let mut request = HttpRequest::default();
+        request.method = method.to_string();
+        request.uri = uri.to_string();
+        request
+    }
+
+    #[test]
+    fn test_panicking_handler_responds_with_500() {
+        let mut router = router();
+        router.add_route(Method::Get, String::from("/panic"), PanickingHandler {});
+
+        let mut response = HttpResponse::default();
+        router.handle(&request("GET", "/panic"), &mut response);
+
+        assert_eq!(response.status, StatusType::InternalServerError);
+        assert!(!response.body.is_empty());
+    }
+
+    #[test]
+    fn test_custom_error_handler() {
+        let mut router = router();
+        router
+            .add_route(
+                Method::Get,
+                String::from("/"),
+                PlainTextHandler { text: "index" },
+            )
+            .add_error_handler(StatusType::NotFound, PlainTextHandler { text: "nothing" });
+
+        let mut response = HttpResponse::default();
+        router.handle(&request("GET", "/missing"), &mut response);
+
+        assert_eq!(response.status, StatusType::NotFound);
+        assert_eq!(response.body, b"nothing");
+    }
+
+    #[test]
+    fn test_panicking_error_handler_uses_default_page() {
+        let mut router = router();
+        router
+            .add_route(
+                Method::Get,
+                String::from("/"),
+                PlainTextHandler { text: "index" },
+            )
+            .add_error_handler(StatusType::NotFound, PanickingHandler {});
+
+        let mut response = HttpResponse::default();
+        router.handle(&request("GET", "/missing"), &mut response);
+
+        assert_eq!(response.status, StatusType::NotFound);
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert!(!response.body.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_method_uses_error_page() {
+        let router = router();
+
+        let mut response = HttpResponse::default();
+        router.handle(&request("PATCH", "/"), &mut response);
+
+        assert_eq!(response.status, StatusType::MethodNotAllowed);
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+    }
+
+    fn upgrade_request(uri: &str) -> HttpRequest {
+        let mut request = request("GET", uri);
+        request.headers = vec![
+            HttpHeader::new("Upgrade", "websocket"),
+            HttpHeader::new("Connection", "Upgrade"),
+            HttpHeader::new("Sec-WebSocket-Version", "13"),
+            HttpHeader::new("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="),
+        ];
+        request
+    }
+
+    #[test]
+    fn test_route_websocket_upgrade() {
+        let mut router = router();
+        router.add_ws_route(String::from("/chat"), NoopWsHandler {});
+
+        let mut response = HttpResponse::default();
+        let upgrade = router.route_websocket(&upgrade_request("/chat?room=main"), &mut response);
+
+        assert!(upgrade.is_some_and(|upgrade| upgrade.context.protocol.is_none()));
+        assert_eq!(response.status, StatusType::SwitchingProtocols);
+        assert_eq!(
+            response.header("Sec-WebSocket-Accept"),
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+    }
+
+    #[test]
+    fn test_route_websocket_unknown_path() {
+        let mut router = router();
+        router.add_ws_route(String::from("/chat"), NoopWsHandler {});
+
+        let mut response = HttpResponse::default();
+        let upgrade = router.route_websocket(&upgrade_request("/other"), &mut response);
+
+        assert!(upgrade.is_none());
+        assert_eq!(response.status, StatusType::NotFound);
+    }
+
+    #[test]
+    fn test_route_websocket_rejects_foreign_origin() {
+        let mut router = router();
+        router
+            .add_ws_route(String::from("/chat"), NoopWsHandler {})
+            .set_allowed_origins(vec![String::from("http://localhost:6969")]);
+
+        let mut request = upgrade_request("/chat");
+        request
+            .headers
+            .push(HttpHeader::new("Origin", "http://evil.example"));
+        let mut response = HttpResponse::default();
+
+        assert!(router.route_websocket(&request, &mut response).is_none());
+        assert_eq!(response.status, StatusType::Forbidden);
+
+        request.headers.last_mut().unwrap().value = String::from("http://localhost:6969");
+        assert!(router.route_websocket(&request, &mut response).is_some());
+    }
+
+    #[test]
+    fn test_route_websocket_token_auth() {
+        let validator = TokenValidator::new(b"secret");
+        let mut router = router();
+        router
+            .add_ws_route(String::from("/chat"), NoopWsHandler {})
+            .set_token_auth(validator.clone(), true);
+        let mut response = HttpResponse::default();
+
+        assert!(router
+            .route_websocket(&upgrade_request("/chat"), &mut response)
+            .is_none());
+        assert_eq!(response.status, StatusType::Unauthorized);
+
+        let uri = format!("/chat?access_token={}", validator.issue("alice", 60));
+        let upgrade = router
+            .route_websocket(&upgrade_request(&uri), &mut response)
+            .unwrap();
+        assert_eq!(upgrade.context.user.as_deref(), Some("alice"));
+
+        let uri = format!(
+            "/chat?access_token={}",
+            TokenValidator::new(b"x").issue("bob", 60)
+        );
+        assert!(router
+            .route_websocket(&upgrade_request(&uri), &mut response)
+            .is_none());
+    }
+
+    #[test]
+    fn test_route_websocket_echoes_token_protocol() {
+        let validator = TokenValidator::new(b"secret");
+        let mut router = router();
+        router
+            .add_ws_route(String::from("/chat"), NoopWsHandler {})
+            .set_token_auth(validator.clone(), true);
+
+        let token_protocol = format!("access_token.{}", validator.issue("alice", 60));
+        let mut request = upgrade_request("/chat");
+        request
+            .headers
+            .push(HttpHeader::new("Sec-WebSocket-Protocol", &token_protocol));
+        let mut response = HttpResponse::default();
+        let upgrade = router.route_websocket(&request, &mut response).unwrap();
+
+        assert_eq!(upgrade.context.user.as_deref(), Some("alice"));
+        assert_eq!(upgrade.context.protocol, None);
+        assert_eq!(
+            response.header("Sec-WebSocket-Protocol"),
+            Some(token_protocol.as_str())
+        );
+
+        request.headers.last_mut().unwrap().value = format!("{}, chat.v1.json", token_protocol);
+        router.route_websocket(&request, &mut response).unwrap();
+        assert_eq!(
+            response.header("Sec-WebSocket-Protocol"),
+            Some("chat.v1.json")
+        );
+    }
+
+    #[test]
+    fn test_route_websocket_session_cookie() {
+        let sessions = Arc::new(SessionStore::new(std::time::Duration::from_secs(60)));
+        let mut router = router();
+        router
+            .add_ws_route(String::from("/chat"), NoopWsHandler {})
+            .set_session_store(Arc::clone(&sessions));
+
+        let mut request = upgrade_request("/chat");
+        request.headers.push(HttpHeader::new(
+            "Cookie",
+            &format!("{}={}", SESSION_COOKIE, sessions.create("alice")),
+        ));
+        let mut response = HttpResponse::default();
+        let upgrade = router.route_websocket(&request, &mut response).unwrap();
+
+        assert_eq!(upgrade.context.user.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn test_route_websocket_negotiates_protocol() {
+        let mut router = router();
+        router.add_ws_route(String::from("/chat"), NoopWsHandler {});
+
+        let mut request = upgrade_request("/chat");
+        request.headers.push(HttpHeader::new(
+            "Sec-WebSocket-Protocol",
+            "chat.v2.json, chat.v1.msgpack, chat.v1.json",
+        ));
+        let mut response = HttpResponse::default();
+        let upgrade = router.route_websocket(&request, &mut response).unwrap();
+
+        assert_eq!(upgrade.context.protocol.as_deref(), Some("chat.v1.msgpack"));
+        assert_eq!(
+            response.header("Sec-WebSocket-Protocol"),
+            Some("chat.v1.msgpack")
+        );
+    }
+
+    #[test]
+    fn test_default_error_page() {
+        let page = default_error_page(StatusType::NotFound);
 
-        *response = HttpResponse::new(status, headers, file_content.to_vec());
+        assert!(page.contains("<h1>404 Not Found</h1>"));
     }
 }
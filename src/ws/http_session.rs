@@ -1,20 +1,31 @@
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{timeout_at, Instant};
 use tokio::{io::AsyncReadExt, io::AsyncWriteExt, net::TcpStream};
 
-use crate::ws::http_header::HttpHeader;
 use crate::ws::http_request::HttpRequest;
 use crate::ws::http_request_parser::{HttpRequestParser, ParseResult};
-use crate::ws::http_response::HttpResponse;
+use crate::ws::http_response::{HttpResponse, StatusType};
 use crate::ws::http_router::HttpRouter;
+use crate::ws::ws_handler::WsUpgrade;
+
+// Request bodies are only used for small forms.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+// Request line and headers together, the parser keeps everything it is sent.
+const MAX_HEADER_SIZE: usize = 16 * 1024;
+// Time a client gets to send the whole request, so slow clients can't hold
+// connections open.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(PartialEq, Eq)]
 pub enum HttpHandleError {
-    WebsocketProtocol,
     ParseRequestError,
     SocketConnectionError,
 }
 
-pub type HandleResult = std::result::Result<(), HttpHandleError>;
+// On websocket upgrade the handshake response is already sent and the caller is
+// expected to hand the socket over to the returned upgrade.
+pub type HandleResult = std::result::Result<Option<WsUpgrade>, HttpHandleError>;
 
 #[derive(Clone)]
 pub struct HttpSession {
@@ -34,79 +45,179 @@ impl HttpSession {
         }
     }
 
-    async fn do_response(&self, socket: &mut TcpStream, remote_addr: &str) {
+    async fn do_response(&self, socket: &mut TcpStream, remote_addr: &str) -> bool {
         match socket.write_all(&self.response.bytes()[..]).await {
-            Ok(_) => (),
+            Ok(_) => true,
             Err(e) => {
                 eprintln!(
                     "Http respond can't be sent to client: {}, error: {}",
                     remote_addr, e
                 );
+                false
             }
         }
     }
 
     pub async fn handle_socket(&mut self, socket: &mut TcpStream) -> HandleResult {
         let remote_addr = match socket.peer_addr() {
-            Ok(remote) => format!("{}:{}", remote.ip().to_string(), remote.port()),
+            Ok(remote) => {
+                self.request.remote_ip = Some(remote.ip());
+                format!("{}:{}", remote.ip(), remote.port())
+            }
             Err(e) => {
                 eprintln!("Can't get remote address, error: {}", e);
                 return Err(HttpHandleError::SocketConnectionError);
             }
         };
 
+        let deadline = Instant::now() + REQUEST_TIMEOUT;
         let mut buffer = [0; 1024];
-        let mut total = 0;
-
-        while let Ok(n) = socket.peek(&mut buffer).await {
-            if n == 0 {
-                eprintln!("Can't read any data from client: {}", remote_addr);
-                return Err(HttpHandleError::SocketConnectionError);
-            }
-
-            let input = if let Ok(input) = String::from_utf8(buffer[..n].to_vec()) {
-                input
-            } else {
-                eprintln!("Can't convert request to valid utf8 charactre");
-                return Err(HttpHandleError::ParseRequestError);
+        let mut header_size = 0;
+        let leftover = loop {
+            let n = match timeout_at(deadline, socket.read(&mut buffer)).await {
+                Ok(Ok(0) | Err(_)) => {
+                    eprintln!("Can't read any data from client: {}", remote_addr);
+                    return Err(HttpHandleError::SocketConnectionError);
+                }
+                Ok(Ok(n)) => n,
+                Err(_) => {
+                    eprintln!("Timed out reading request from client: {}", remote_addr);
+                    return Err(HttpHandleError::SocketConnectionError);
+                }
             };
 
-            total += n;
-
-            match self.request_parser.parse(&mut self.request, input.chars()) {
-                ParseResult::Ok => {
-                    break;
-                }
-                ParseResult::Indeterminate => continue,
+            // Request line and headers are ASCII, bytes are fed to the parser one to one
+            // so whatever follows the headers can be handed over untouched.
+            let mut input = buffer[..n].iter();
+            let result = self
+                .request_parser
+                .parse(&mut self.request, input.by_ref().map(|&b| b as char));
+            header_size += n - input.len();
+            match result {
                 ParseResult::Bad => {
                     eprintln!("Can't parse request from client: {}", remote_addr);
                     return Err(HttpHandleError::ParseRequestError);
                 }
+                _ if header_size > MAX_HEADER_SIZE => {
+                    eprintln!("Request headers of client: {} are too large", remote_addr);
+                    self.response =
+                        HttpResponse::new(StatusType::RequestHeaderFieldsTooLarge, vec![], vec![]);
+                    self.do_response(socket, &remote_addr).await;
+                    return Ok(None);
+                }
+                ParseResult::Ok => break input.as_slice().to_vec(),
+                ParseResult::Indeterminate => continue,
             }
+        };
+
+        if is_websocket_request(&self.request) {
+            let upgrade = self
+                .router
+                .route_websocket(&self.request, &mut self.response);
+            if !self.do_response(socket, &remote_addr).await {
+                return Err(HttpHandleError::SocketConnectionError);
+            }
+
+            return Ok(upgrade.map(|upgrade| WsUpgrade {
+                leftover,
+                ..upgrade
+            }));
         }
 
-        if is_websocket_request(&self.request.headers) {
-            return Err(HttpHandleError::WebsocketProtocol);
+        let content_length = self.request.content_length().unwrap_or(0);
+        if content_length > MAX_BODY_SIZE {
+            self.response = HttpResponse::new(StatusType::PayloadTooLarge, vec![], vec![]);
+            self.do_response(socket, &remote_addr).await;
+            return Ok(None);
         }
 
-        if !cleanup_socket_data(socket, total).await {
-            eprintln!("Can't cleanup socket");
-            return Err(HttpHandleError::SocketConnectionError);
+        let mut body = leftover;
+        while body.len() < content_length {
+            match timeout_at(deadline, socket.read(&mut buffer)).await {
+                Ok(Ok(0) | Err(_)) => {
+                    eprintln!("Can't read request body from client: {}", remote_addr);
+                    return Err(HttpHandleError::SocketConnectionError);
+                }
+                Ok(Ok(n)) => body.extend_from_slice(&buffer[..n]),
+                Err(_) => {
+                    eprintln!(
+                        "Timed out reading request body from client: {}",
+                        remote_addr
+                    );
+                    return Err(HttpHandleError::SocketConnectionError);
+                }
+            }
         }
+        body.truncate(content_length);
+        self.request.body = String::from_utf8_lossy(&body).into_owned();
 
         self.router.handle(&self.request, &mut self.response);
         self.do_response(socket, &remote_addr).await;
-        Ok(())
+        Ok(None)
     }
 }
 
-fn is_websocket_request(headers: &Vec<HttpHeader>) -> bool {
-    return headers
-        .iter()
-        .any(|header| header.name == "Upgrade" && header.value == "websocket");
+fn is_websocket_request(request: &HttpRequest) -> bool {
+    request.header("Upgrade").is_some_and(|upgrade| {
+        upgrade
+            .split(',')
+            .any(|protocol| protocol.trim().eq_ignore_ascii_case("websocket"))
+    })
 }
 
-async fn cleanup_socket_data(socket: &mut TcpStream, n: usize) -> bool {
-    let mut discard_buff = vec![0; n];
-    socket.read_exact(&mut discard_buff).await.is_ok()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::file_storage::FileStorage;
+    use std::path::Path;
+    use tokio::net::TcpListener;
+
+    // Sends `request` to a session and returns the status line of its response.
+    async fn respond(request: String) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = tokio::spawn(async move {
+            let mut socket = TcpStream::connect(address).await.unwrap();
+            socket.write_all(request.as_bytes()).await.unwrap();
+            let mut response = Vec::new();
+            socket.read_to_end(&mut response).await.unwrap();
+            String::from_utf8_lossy(&response).into_owned()
+        });
+
+        let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+        let router = HttpRouter::new(Arc::new(FileStorage::new(&assets).unwrap()));
+        let (mut socket, _) = listener.accept().await.unwrap();
+        HttpSession::new(Arc::new(router))
+            .handle_socket(&mut socket)
+            .await
+            .ok();
+        drop(socket);
+
+        let response = client.await.unwrap();
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_large_headers_are_rejected() {
+        let request = format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nCookie: {}\r\n\r\n",
+            "a".repeat(MAX_HEADER_SIZE)
+        );
+
+        assert_eq!(
+            respond(request).await,
+            "HTTP/1.1 431 Request Header Fields Too Large"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_headers_within_limit_are_served() {
+        let request = format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nCookie: {}\r\n\r\n",
+            "a".repeat(MAX_HEADER_SIZE / 2)
+        );
+
+        // The router has no routes.
+        assert_eq!(respond(request).await, "HTTP/1.1 405 Method Not Allowed");
+    }
 }
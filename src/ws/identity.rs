@@ -0,0 +1,18 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_GUEST: AtomicU64 = AtomicU64::new(1);
+
+// Who stands behind a nickname. The sessions logged in to one account share it, a guest
+// nickname belongs to a single connection.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Identity {
+    Account(String),
+    Guest(u64),
+}
+
+impl Identity {
+    // A guest identity no other connection has.
+    pub fn guest() -> Self {
+        Self::Guest(NEXT_GUEST.fetch_add(1, Ordering::Relaxed))
+    }
+}
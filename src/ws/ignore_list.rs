@@ -0,0 +1,157 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::ws::line_file::LineFile;
+
+// Nicknames one user can ignore, keeps the file from growing without bounds.
+pub const MAX_IGNORED: usize = 100;
+
+// Nicknames ignored by registered users, one "<owner> <ignored nickname>" line per pair.
+pub struct IgnoreList {
+    file: LineFile,
+    ignored: Mutex<HashMap<String, BTreeSet<String>>>,
+}
+
+impl IgnoreList {
+    pub fn new(path: &Path) -> std::io::Result<Self> {
+        let (file, pairs) = LineFile::open(path, "ignored nicknames", |line| {
+            let (owner, nick) = line.split_once(' ')?;
+            (!owner.is_empty() && !nick.is_empty()).then(|| (owner.to_string(), nick.to_string()))
+        })?;
+
+        let mut ignored: HashMap<String, BTreeSet<String>> = HashMap::new();
+        for (owner, nick) in pairs {
+            ignored.entry(owner).or_default().insert(nick);
+        }
+
+        Ok(Self {
+            file,
+            ignored: Mutex::new(ignored),
+        })
+    }
+
+    pub fn ignored(&self, owner: &str) -> HashSet<String> {
+        self.ignored
+            .lock()
+            .unwrap()
+            .get(owner)
+            .map(|nicks| nicks.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // False if `owner` already ignored `nick`.
+    pub fn ignore(&self, owner: &str, nick: &str) -> std::io::Result<bool> {
+        self.ignore_all(owner, &[nick.to_string()])
+            .map(|added| added > 0)
+    }
+
+    // Adds every nickname with one write, returns how many were not ignored yet.
+    pub fn ignore_all(&self, owner: &str, nicks: &[String]) -> std::io::Result<usize> {
+        let saving = self.file.saving();
+        let (added, content) = {
+            let mut ignored = self.ignored.lock().unwrap();
+            let owned = ignored.entry(owner.to_string()).or_default();
+            let added: Vec<&String> = nicks
+                .iter()
+                .filter(|nick| owned.insert(nick.to_string()))
+                .collect();
+            if added.is_empty() {
+                return Ok(0);
+            }
+            (added, render(&ignored))
+        };
+
+        if let Err(e) = saving.write(&content) {
+            if let Some(owned) = self.ignored.lock().unwrap().get_mut(owner) {
+                for nick in &added {
+                    owned.remove(*nick);
+                }
+            }
+            return Err(e);
+        }
+        Ok(added.len())
+    }
+
+    // False if `owner` did not ignore `nick`.
+    pub fn unignore(&self, owner: &str, nick: &str) -> std::io::Result<bool> {
+        let saving = self.file.saving();
+        let content = {
+            let mut ignored = self.ignored.lock().unwrap();
+            let removed = ignored
+                .get_mut(owner)
+                .is_some_and(|nicks| nicks.remove(nick));
+            if !removed {
+                return Ok(false);
+            }
+
+            ignored.retain(|_, nicks| !nicks.is_empty());
+            render(&ignored)
+        };
+        saving.write(&content).map(|()| true)
+    }
+}
+
+fn render(ignored: &HashMap<String, BTreeSet<String>>) -> String {
+    ignored
+        .iter()
+        .flat_map(|(owner, nicks)| {
+            nicks
+                .iter()
+                .map(move |nick| format!("{} {}\n", owner, nick))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::test_util::TempDir;
+
+    #[test]
+    fn test_ignores_are_persisted() {
+        let dir = TempDir::new("ignore");
+        let path = dir.join("ignores.txt");
+        let ignore_list = IgnoreList::new(&path).unwrap();
+        assert!(ignore_list.ignore("alice", "mallory").unwrap());
+        assert!(!ignore_list.ignore("alice", "mallory").unwrap());
+        ignore_list.ignore("alice", "eve").unwrap();
+        ignore_list.ignore("bob", "eve").unwrap();
+
+        let ignore_list = IgnoreList::new(&path).unwrap();
+
+        assert_eq!(
+            ignore_list.ignored("alice"),
+            HashSet::from([String::from("eve"), String::from("mallory")])
+        );
+        assert!(ignore_list.ignored("carol").is_empty());
+    }
+
+    #[test]
+    fn test_ignore_all() {
+        let dir = TempDir::new("ignore_all");
+        let path = dir.join("ignores.txt");
+        let ignore_list = IgnoreList::new(&path).unwrap();
+        ignore_list.ignore("alice", "eve").unwrap();
+
+        let nicks = [String::from("eve"), String::from("mallory")];
+        assert_eq!(ignore_list.ignore_all("alice", &nicks).unwrap(), 1);
+        assert_eq!(ignore_list.ignore_all("alice", &nicks).unwrap(), 0);
+        assert_eq!(
+            IgnoreList::new(&path).unwrap().ignored("alice"),
+            HashSet::from(nicks)
+        );
+    }
+
+    #[test]
+    fn test_unignore() {
+        let dir = TempDir::new("unignore");
+        let path = dir.join("ignores.txt");
+        let ignore_list = IgnoreList::new(&path).unwrap();
+        ignore_list.ignore("alice", "mallory").unwrap();
+
+        assert!(ignore_list.unignore("alice", "mallory").unwrap());
+        assert!(!ignore_list.unignore("alice", "mallory").unwrap());
+        assert!(IgnoreList::new(&path).unwrap().ignored("alice").is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+use std::fs::{create_dir_all, read_to_string, write, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+// Text file with one record per line, where the stores keep their state. Writes happen
+// on the calling thread, so async callers run store changes on a blocking thread.
+pub struct LineFile {
+    path: PathBuf,
+    // Held while the file is written, so the store's data lock is not held across disk
+    // writes and writes land in order.
+    saving: Mutex<()>,
+}
+
+// Turn to write the file. Taken before the new content is rendered, so a later change
+// can't be overwritten by an earlier one.
+pub struct Saving<'a> {
+    path: &'a Path,
+    _turn: MutexGuard<'a, ()>,
+}
+
+impl LineFile {
+    // Reads the records with `parse`, skipping empty lines and the ones it rejects.
+    // `records` names them in the log.
+    pub fn open<T, F>(path: &Path, records: &str, mut parse: F) -> std::io::Result<(Self, Vec<T>)>
+    where
+        F: FnMut(&str) -> Option<T>,
+    {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+
+        let mut parsed = Vec::new();
+        if path.exists() {
+            for line in read_to_string(path)?.lines() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match parse(line) {
+                    Some(record) => parsed.push(record),
+                    None => eprintln!("Skipping malformed line in {:?}", path),
+                }
+            }
+        }
+        println!("Loaded {} {} from {:?}.", parsed.len(), records, path);
+
+        let file = Self {
+            path: path.to_path_buf(),
+            saving: Mutex::new(()),
+        };
+        Ok((file, parsed))
+    }
+
+    pub fn saving(&self) -> Saving<'_> {
+        Saving {
+            path: &self.path,
+            _turn: self.saving.lock().unwrap(),
+        }
+    }
+}
+
+impl Saving<'_> {
+    // Replaces the whole file.
+    pub fn write(&self, content: &str) -> std::io::Result<()> {
+        write(self.path, content)
+    }
+
+    pub fn append(&self, line: &str) -> std::io::Result<()> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path)
+            .and_then(|mut file| writeln!(file, "{}", line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::test_util::TempDir;
+
+    #[test]
+    fn test_open_skips_malformed_lines() {
+        let dir = TempDir::new("line_file");
+        let path = dir.join("nested/records.txt");
+        let (file, records) =
+            LineFile::open(&path, "numbers", |line| line.parse::<u32>().ok()).unwrap();
+        assert!(records.is_empty());
+
+        file.saving().write("1\n\nnot a number\n").unwrap();
+        file.saving().append("2").unwrap();
+
+        let (_, records) =
+            LineFile::open(&path, "numbers", |line| line.parse::<u32>().ok()).unwrap();
+        assert_eq!(records, vec![1, 2]);
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use crate::ws::handler::Handler;
+use crate::ws::http_cookie::SetCookie;
+use crate::ws::http_header::HttpHeader;
+use crate::ws::http_request::HttpRequest;
+use crate::ws::http_response::{HttpResponse, StatusType};
+use crate::ws::rate_limit::{IpRateLimiter, RateLimit};
+use crate::ws::session_store::{SessionStore, SESSION_COOKIE};
+use crate::ws::user_store::UserStore;
+
+// Login attempts per client address, slows down guessing passwords.
+pub const DEFAULT_LOGIN_LIMIT: RateLimit = RateLimit::new(5, 0.2);
+
+// Handles the login form: "nick" and "password" fields, form url encoded. On success
+// the browser gets a session cookie and is redirected to the chat.
+pub struct LoginHandler {
+    users: Arc<dyn UserStore + Send + Sync>,
+    sessions: Arc<SessionStore>,
+    redirect_to: String,
+    limiter: Arc<IpRateLimiter>,
+}
+
+impl LoginHandler {
+    pub fn new(
+        users: Arc<dyn UserStore + Send + Sync>,
+        sessions: Arc<SessionStore>,
+        redirect_to: String,
+        limiter: Arc<IpRateLimiter>,
+    ) -> Self {
+        Self {
+            users,
+            sessions,
+            redirect_to,
+            limiter,
+        }
+    }
+}
+
+impl Handler for LoginHandler {
+    fn handle(&self, request: &HttpRequest, response: &mut HttpResponse) {
+        let (nick, password) = match (request.form_param("nick"), request.form_param("password")) {
+            (Some(nick), Some(password)) => (nick, password),
+            _ => {
+                *response = text_response(StatusType::BadRequest, "Missing nick or password");
+                return;
+            }
+        };
+
+        if request
+            .remote_ip
+            .is_some_and(|ip| !self.limiter.try_take(ip))
+        {
+            *response = text_response(StatusType::TooManyRequests, "Too many login attempts");
+            return;
+        }
+
+        // Password hashing is slow on purpose, other tasks move to another worker meanwhile.
+        let verified = tokio::task::block_in_place(|| self.users.verify(&nick, &password));
+        if !verified {
+            *response = text_response(StatusType::Unauthorized, "Invalid nickname or password");
+            return;
+        }
+
+        let cookie = SetCookie {
+            max_age: Some(self.sessions.ttl().as_secs()),
+            ..SetCookie::new(SESSION_COOKIE, &self.sessions.create(&nick))
+        };
+        *response = redirect_response(&self.redirect_to);
+        response.add_cookie(&cookie);
+    }
+}
+
+pub struct LogoutHandler {
+    sessions: Arc<SessionStore>,
+    redirect_to: String,
+}
+
+impl LogoutHandler {
+    pub fn new(sessions: Arc<SessionStore>, redirect_to: String) -> Self {
+        Self {
+            sessions,
+            redirect_to,
+        }
+    }
+}
+
+impl Handler for LogoutHandler {
+    fn handle(&self, request: &HttpRequest, response: &mut HttpResponse) {
+        if let Some(id) = request.cookie(SESSION_COOKIE) {
+            self.sessions.remove(id);
+        }
+
+        *response = redirect_response(&self.redirect_to);
+        response.add_cookie(&SetCookie::expired(SESSION_COOKIE));
+    }
+}
+
+fn redirect_response(location: &str) -> HttpResponse {
+    HttpResponse::new(
+        StatusType::SeeOther,
+        vec![HttpHeader::new("Location", location)],
+        Vec::new(),
+    )
+}
+
+fn text_response(status: StatusType, text: &str) -> HttpResponse {
+    HttpResponse::new(
+        status,
+        vec![HttpHeader::new("Content-Type", "text/plain; charset=utf-8")],
+        text.as_bytes().to_vec(),
+    )
+}
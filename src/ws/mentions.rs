@@ -0,0 +1,60 @@
+// Mentions that notify everyone in the chat instead of one nickname.
+pub const MENTION_EVERYONE: [&str; 2] = ["here", "all"];
+
+pub fn is_mention_everyone(mention: &str) -> bool {
+    MENTION_EVERYONE.contains(&mention)
+}
+
+// Words of `message` starting with @ that `is_known` accepts, without the @ and in order
+// of first mention. Punctuation right after the name is ignored, as in "@alice, hi".
+pub fn parse_mentions<F>(message: &str, is_known: F) -> Vec<String>
+where
+    F: Fn(&str) -> bool,
+{
+    let mut mentions: Vec<String> = Vec::new();
+    for word in message.split_whitespace() {
+        let Some(mut name) = word.strip_prefix('@') else {
+            continue;
+        };
+
+        while !name.is_empty() && !is_known(name) {
+            name = name
+                .strip_suffix(|c: char| c.is_ascii_punctuation())
+                .unwrap_or_default();
+        }
+        if !name.is_empty() && !mentions.iter().any(|mention| mention == name) {
+            mentions.push(name.to_string());
+        }
+    }
+    mentions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn known(name: &str) -> bool {
+        ["alice", "bob", "x.y", "here"].contains(&name)
+    }
+
+    #[test]
+    fn test_parse_mentions() {
+        assert_eq!(
+            parse_mentions("@bob, @alice: hi @bob @carol", known),
+            vec!["bob", "alice"]
+        );
+        assert_eq!(
+            parse_mentions("mail alice@bob.com", known),
+            Vec::<String>::new()
+        );
+        assert_eq!(parse_mentions("@x.y. @here!", known), vec!["x.y", "here"]);
+        assert_eq!(parse_mentions("@ @!!", known), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_mention_everyone() {
+        assert!(is_mention_everyone("here"));
+        assert!(is_mention_everyone("all"));
+        assert!(!is_mention_everyone("alice"));
+    }
+}
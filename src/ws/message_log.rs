@@ -0,0 +1,234 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::ws::identity::Identity;
+
+// Oldest messages are forgotten past this.
+pub const MAX_LOGGED: usize = 10_000;
+// Different reactions on one message.
+pub const MAX_REACTIONS: usize = 20;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReactError {
+    NoMessage,
+    TooManyReactions,
+}
+
+impl std::fmt::Display for ReactError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoMessage => write!(f, "message does not exist"),
+            Self::TooManyReactions => write!(
+                f,
+                "message already has {} different reactions",
+                MAX_REACTIONS
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SentMessage {
+    pub sender: String,
+    // Who may change the message, the sender nickname can change hands.
+    pub author: Identity,
+    // None for chat messages, they went to everyone.
+    pub receiver: Option<String>,
+    pub message: String,
+    // Unix time.
+    pub sent_at: u64,
+    pub reply_to: Option<u64>,
+    // Id of the first message of the thread, None if this one is.
+    pub thread: Option<u64>,
+    // Nicknames by reaction.
+    pub reactions: BTreeMap<String, BTreeSet<String>>,
+    pub mentions: Vec<String>,
+}
+
+#[derive(Default)]
+struct Logged {
+    messages: HashMap<u64, SentMessage>,
+    // Ids in the order they were sent, to forget the oldest ones.
+    order: VecDeque<u64>,
+}
+
+// Recent chat and private messages, to check who may change them and to find threads.
+#[derive(Default)]
+pub struct MessageLog {
+    logged: Mutex<Logged>,
+}
+
+impl MessageLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, id: u64, message: SentMessage) {
+        let mut logged = self.logged.lock().unwrap();
+        while logged.order.len() >= MAX_LOGGED {
+            if let Some(oldest) = logged.order.pop_front() {
+                logged.messages.remove(&oldest);
+            }
+        }
+
+        logged.order.push_back(id);
+        logged.messages.insert(id, message);
+    }
+
+    pub fn get(&self, id: u64) -> Option<SentMessage> {
+        self.logged.lock().unwrap().messages.get(&id).cloned()
+    }
+
+    pub fn edit(&self, id: u64, message: &str) {
+        if let Some(sent) = self.logged.lock().unwrap().messages.get_mut(&id) {
+            sent.message = message.to_string();
+        }
+    }
+
+    // Adds or removes the reaction of `nick`, returns the message if that changed it.
+    pub fn react(
+        &self,
+        id: u64,
+        reaction: &str,
+        nick: &str,
+        reacted: bool,
+    ) -> Result<Option<SentMessage>, ReactError> {
+        let mut logged = self.logged.lock().unwrap();
+        let sent = logged.messages.get_mut(&id).ok_or(ReactError::NoMessage)?;
+
+        let changed = if reacted {
+            if !sent.reactions.contains_key(reaction) && sent.reactions.len() >= MAX_REACTIONS {
+                return Err(ReactError::TooManyReactions);
+            }
+            sent.reactions
+                .entry(reaction.to_string())
+                .or_default()
+                .insert(nick.to_string())
+        } else {
+            let removed = sent
+                .reactions
+                .get_mut(reaction)
+                .is_some_and(|nicks| nicks.remove(nick));
+            sent.reactions.retain(|_, nicks| !nicks.is_empty());
+            removed
+        };
+
+        Ok(changed.then(|| sent.clone()))
+    }
+
+    pub fn remove(&self, id: u64) {
+        let mut logged = self.logged.lock().unwrap();
+        if logged.messages.remove(&id).is_some() {
+            logged.order.retain(|logged_id| *logged_id != id);
+        }
+    }
+
+    // Chat messages of the thread started by `root`, oldest first, without the root if it
+    // was deleted or forgotten.
+    pub fn thread(&self, root: u64) -> Vec<(u64, SentMessage)> {
+        let logged = self.logged.lock().unwrap();
+        logged
+            .order
+            .iter()
+            .filter_map(|id| logged.messages.get(id).map(|sent| (*id, sent)))
+            .filter(|(id, sent)| *id == root || sent.thread == Some(root))
+            .filter(|(_, sent)| sent.receiver.is_none())
+            .map(|(id, sent)| (id, sent.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent(message: &str, reply_to: Option<u64>, thread: Option<u64>) -> SentMessage {
+        SentMessage {
+            sender: String::from("alice"),
+            author: Identity::Account(String::from("alice")),
+            receiver: None,
+            message: message.to_string(),
+            sent_at: 1_700_000_000,
+            reply_to,
+            thread,
+            reactions: BTreeMap::new(),
+            mentions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_edit_and_remove() {
+        let log = MessageLog::new();
+        log.record(1, sent("helo", None, None));
+        log.record(2, sent("oops", None, None));
+
+        log.edit(1, "hello");
+        log.remove(2);
+
+        assert_eq!(log.get(1).unwrap().message, "hello");
+        assert_eq!(log.get(2), None);
+    }
+
+    #[test]
+    fn test_thread() {
+        let log = MessageLog::new();
+        log.record(1, sent("root", None, None));
+        log.record(2, sent("other", None, None));
+        log.record(3, sent("reply", Some(1), Some(1)));
+        log.record(4, sent("nested reply", Some(3), Some(1)));
+        log.record(
+            5,
+            SentMessage {
+                receiver: Some(String::from("bob")),
+                ..sent("private", None, Some(1))
+            },
+        );
+
+        let ids: Vec<u64> = log.thread(1).into_iter().map(|(id, _)| id).collect();
+
+        assert_eq!(ids, vec![1, 3, 4]);
+    }
+
+    #[test]
+    fn test_reactions() {
+        let log = MessageLog::new();
+        log.record(1, sent("hi", None, None));
+
+        assert!(log.react(1, "+1", "bob", true).unwrap().is_some());
+        assert!(log.react(1, "+1", "bob", true).unwrap().is_none());
+        let sent = log.react(1, "+1", "carol", true).unwrap().unwrap();
+        assert_eq!(sent.reactions["+1"].len(), 2);
+
+        log.react(1, "+1", "bob", false).unwrap();
+        let sent = log.react(1, "+1", "carol", false).unwrap().unwrap();
+        assert!(sent.reactions.is_empty());
+        assert!(log.react(1, "+1", "carol", false).unwrap().is_none());
+        assert_eq!(log.react(2, "+1", "bob", true), Err(ReactError::NoMessage));
+    }
+
+    #[test]
+    fn test_reactions_are_bounded() {
+        let log = MessageLog::new();
+        log.record(1, sent("hi", None, None));
+        for reaction in 0..MAX_REACTIONS {
+            log.react(1, &reaction.to_string(), "bob", true).unwrap();
+        }
+
+        assert_eq!(
+            log.react(1, "new", "bob", true),
+            Err(ReactError::TooManyReactions)
+        );
+        assert!(log.react(1, "0", "carol", true).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_log_is_bounded() {
+        let log = MessageLog::new();
+        for id in 0..=MAX_LOGGED as u64 {
+            log.record(id, sent("hi", None, None));
+        }
+
+        assert_eq!(log.get(0), None);
+        assert!(log.get(1).is_some());
+    }
+}
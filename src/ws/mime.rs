@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+const DEFAULT_MIME_TYPES: &[(&str, &str)] = &[
+    // Documents
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    ("xml", "application/xml"),
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("pdf", "application/pdf"),
+    ("wasm", "application/wasm"),
+    // Images
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("ico", "image/x-icon"),
+    ("svg", "image/svg+xml"),
+    // Fonts
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("eot", "application/vnd.ms-fontobject"),
+    // Media
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    // Archives
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+];
+
+const MAGIC_BYTES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xFF\xD8\xFF", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"\x00\x00\x01\x00", "image/x-icon"),
+    (b"%PDF-", "application/pdf"),
+    (b"\x00asm", "application/wasm"),
+    (b"wOFF", "font/woff"),
+    (b"wOF2", "font/woff2"),
+    (b"\x00\x01\x00\x00", "font/ttf"),
+    (b"OTTO", "font/otf"),
+    (b"ID3", "audio/mpeg"),
+    (b"OggS", "audio/ogg"),
+    (b"\x1A\x45\xDF\xA3", "video/webm"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1F\x8B", "application/gzip"),
+];
+
+#[derive(Clone)]
+pub struct MimeRegistry {
+    types: HashMap<String, String>,
+}
+
+impl MimeRegistry {
+    pub fn new() -> Self {
+        Self {
+            types: DEFAULT_MIME_TYPES
+                .iter()
+                .map(|(extension, mime)| (extension.to_string(), mime.to_string()))
+                .collect(),
+        }
+    }
+
+    // Overrides or extends the table, `extension` is accepted with or without the dot.
+    pub fn register(&mut self, extension: &str, mime: &str) -> &mut Self {
+        self.types.insert(
+            extension.trim_start_matches('.').to_ascii_lowercase(),
+            mime.to_string(),
+        );
+        self
+    }
+
+    // Only the last extension counts, so `jquery.min.js` is `.js`.
+    pub fn lookup(&self, file_name: &str) -> Option<&str> {
+        let (_, extension) = file_name.rsplit_once('.')?;
+        self.types
+            .get(&extension.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    pub fn content_type(&self, file_name: &str, content: &[u8]) -> String {
+        let mime = self
+            .lookup(file_name)
+            .unwrap_or_else(|| sniff_mime_type(content));
+        with_charset(mime)
+    }
+}
+
+pub fn sniff_mime_type(content: &[u8]) -> &'static str {
+    if let Some((_, mime)) = MAGIC_BYTES
+        .iter()
+        .find(|(magic, _)| content.starts_with(magic))
+    {
+        return mime;
+    }
+
+    if content.len() >= 12 && &content[..4] == b"RIFF" {
+        match &content[8..12] {
+            b"WEBP" => return "image/webp",
+            b"WAVE" => return "audio/wav",
+            _ => {}
+        }
+    }
+
+    if content.len() >= 12 && &content[4..8] == b"ftyp" {
+        return match &content[8..12] {
+            b"avif" => "image/avif",
+            _ => "video/mp4",
+        };
+    }
+
+    let text = match std::str::from_utf8(content) {
+        Ok(text) => text,
+        Err(_) => return DEFAULT_MIME_TYPE,
+    };
+
+    let start = text
+        .trim_start_matches('\u{feff}')
+        .trim_start()
+        .chars()
+        .take(16)
+        .collect::<String>()
+        .to_ascii_lowercase();
+
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        "text/html"
+    } else if start.starts_with("<svg") {
+        "image/svg+xml"
+    } else if start.starts_with("<?xml") {
+        "application/xml"
+    } else if text
+        .chars()
+        .all(|c| !c.is_control() || c.is_ascii_whitespace())
+    {
+        "text/plain"
+    } else {
+        DEFAULT_MIME_TYPE
+    }
+}
+
+fn with_charset(mime: &str) -> String {
+    let is_text = mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || matches!(mime, "application/json" | "application/xml");
+
+    if is_text && !mime.contains(';') {
+        format!("{}; charset=utf-8", mime)
+    } else {
+        mime.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_uses_last_extension() {
+        let registry = MimeRegistry::new();
+
+        assert_eq!(registry.lookup("jquery.min.js"), Some("text/javascript"));
+        assert_eq!(registry.lookup("font.WOFF2"), Some("font/woff2"));
+        assert_eq!(registry.lookup("LICENSE"), None);
+    }
+
+    #[test]
+    fn test_content_type_adds_charset_to_text() {
+        let registry = MimeRegistry::new();
+
+        assert_eq!(
+            registry.content_type("index.html", b""),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(registry.content_type("favicon.png", b""), "image/png");
+    }
+
+    #[test]
+    fn test_register_overrides_default() {
+        let mut registry = MimeRegistry::new();
+        registry
+            .register(".js", "application/javascript")
+            .register("chat", "application/x-chat-log");
+
+        assert_eq!(registry.lookup("script.js"), Some("application/javascript"));
+        assert_eq!(
+            registry.lookup("today.chat"),
+            Some("application/x-chat-log")
+        );
+    }
+
+    #[test]
+    fn test_sniff_unknown_extension() {
+        let registry = MimeRegistry::new();
+
+        assert_eq!(
+            registry.content_type("image.bin", b"\x89PNG\r\n\x1a\n\x00\x00"),
+            "image/png"
+        );
+        assert_eq!(
+            registry.content_type("page", b"  <!DOCTYPE html><html></html>"),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(
+            registry.content_type("notes", b"just some notes\n"),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            registry.content_type("blob", b"\x00\xff\x10"),
+            "application/octet-stream"
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::ws::line_file::LineFile;
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub enum BanTarget {
+    Nick(String),
+    Ip(IpAddr),
+}
+
+impl BanTarget {
+    // Anything that parses as an IP address bans the address, otherwise the nickname.
+    pub fn parse(target: &str) -> Self {
+        match target.parse() {
+            Ok(ip) => Self::Ip(ip),
+            Err(_) => Self::Nick(target.to_string()),
+        }
+    }
+
+    fn from_stored(stored: &str) -> Option<Self> {
+        match stored.split_once(':')? {
+            ("nick", nick) => Some(Self::Nick(nick.to_string())),
+            ("ip", ip) => ip.parse().ok().map(Self::Ip),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for BanTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Nick(nick) => write!(f, "nick:{}", nick),
+            Self::Ip(ip) => write!(f, "ip:{}", ip),
+        }
+    }
+}
+
+// Keeps one "<nick:name|ip:address> <expiry as unix time, 0 if permanent>" line per ban.
+pub struct BanList {
+    file: LineFile,
+    bans: Mutex<HashMap<BanTarget, Option<u64>>>,
+}
+
+impl BanList {
+    pub fn new(path: &Path) -> std::io::Result<Self> {
+        let (file, bans) = LineFile::open(path, "bans", |line| {
+            let (target, expires_at) = line.split_once(' ')?;
+            let expires_at = expires_at.trim().parse::<u64>().ok()?;
+            Some((
+                BanTarget::from_stored(target)?,
+                (expires_at != 0).then_some(expires_at),
+            ))
+        })?;
+        let mut bans: HashMap<_, _> = bans.into_iter().collect();
+        bans.retain(|_, expires_at| is_active(*expires_at));
+
+        Ok(Self {
+            file,
+            bans: Mutex::new(bans),
+        })
+    }
+
+    // Bans `target` for `duration`, forever without one. Writes the file, so async
+    // callers run it on a blocking thread.
+    pub fn ban(&self, target: BanTarget, duration: Option<Duration>) -> std::io::Result<()> {
+        let expires_at = duration.map(|duration| now() + duration.as_secs());
+
+        let saving = self.file.saving();
+        let content: String = {
+            let mut bans = self.bans.lock().unwrap();
+            bans.retain(|_, expires_at| is_active(*expires_at));
+            bans.insert(target, expires_at);
+            bans.iter()
+                .map(|(target, expires_at)| format!("{} {}\n", target, expires_at.unwrap_or(0)))
+                .collect()
+        };
+        saving.write(&content)
+    }
+
+    pub fn is_banned(&self, target: &BanTarget) -> bool {
+        self.bans
+            .lock()
+            .unwrap()
+            .get(target)
+            .is_some_and(|expires_at| is_active(*expires_at))
+    }
+}
+
+// Moderation state shared by all sessions of one chat endpoint.
+pub struct Moderation {
+    bans: Arc<BanList>,
+    // Nicknames that are operators once logged in.
+    operators: HashSet<String>,
+    mutes: Mutex<HashMap<String, Option<Instant>>>,
+}
+
+impl Moderation {
+    pub fn new(bans: Arc<BanList>, operators: Vec<String>) -> Self {
+        Self {
+            bans,
+            operators: operators.into_iter().collect(),
+            mutes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn bans(&self) -> &Arc<BanList> {
+        &self.bans
+    }
+
+    pub fn is_nick_banned(&self, nick: &str) -> bool {
+        self.bans.is_banned(&BanTarget::Nick(nick.to_string()))
+    }
+
+    pub fn is_operator(&self, nick: &str) -> bool {
+        self.operators.contains(nick)
+    }
+
+    // Mutes `nick` for `duration`, until the server restarts without one.
+    pub fn mute(&self, nick: &str, duration: Option<Duration>) {
+        self.mutes.lock().unwrap().insert(
+            nick.to_string(),
+            duration.map(|duration| Instant::now() + duration),
+        );
+    }
+
+    pub fn is_muted(&self, nick: &str) -> bool {
+        let mut mutes = self.mutes.lock().unwrap();
+        match mutes.get(nick) {
+            Some(Some(until)) if *until <= Instant::now() => {
+                mutes.remove(nick);
+                false
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+}
+
+// Parses durations like "90", "30s", "10m", "2h" or "7d", plain numbers are seconds.
+pub fn parse_duration(duration: &str) -> Option<Duration> {
+    let duration = duration.trim();
+    let (value, unit) = match duration.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => duration.split_at(index),
+        None => (duration, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+
+    value
+        .parse::<u64>()
+        .ok()
+        .and_then(|value| value.checked_mul(multiplier))
+        .map(Duration::from_secs)
+}
+
+fn is_active(expires_at: Option<u64>) -> bool {
+    expires_at.is_none_or(|expires_at| expires_at > now())
+}
+
+// Unix time in seconds.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::test_util::TempDir;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("10m"), Some(Duration::from_secs(600)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("1d"), Some(Duration::from_secs(86400)));
+        assert_eq!(parse_duration("1w"), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration(""), None);
+    }
+
+    #[test]
+    fn test_ban_target_parse() {
+        assert_eq!(
+            BanTarget::parse("10.0.0.1"),
+            BanTarget::Ip("10.0.0.1".parse().unwrap())
+        );
+        assert_eq!(
+            BanTarget::parse("alice"),
+            BanTarget::Nick(String::from("alice"))
+        );
+    }
+
+    #[test]
+    fn test_bans_are_persisted() {
+        let dir = TempDir::new("bans");
+        let path = dir.join("bans.txt");
+        let bans = BanList::new(&path).unwrap();
+        bans.ban(BanTarget::parse("alice"), None).unwrap();
+        bans.ban(BanTarget::parse("::1"), Some(Duration::from_secs(3600)))
+            .unwrap();
+        bans.ban(BanTarget::parse("bob"), Some(Duration::ZERO))
+            .unwrap();
+
+        let bans = BanList::new(&path).unwrap();
+
+        assert!(bans.is_banned(&BanTarget::parse("alice")));
+        assert!(bans.is_banned(&BanTarget::parse("::1")));
+        assert!(!bans.is_banned(&BanTarget::parse("bob")));
+        assert!(!bans.is_banned(&BanTarget::parse("carol")));
+    }
+
+    #[test]
+    fn test_mute_expires() {
+        let dir = TempDir::new("mute");
+        let moderation = Moderation::new(
+            Arc::new(BanList::new(&dir.join("bans.txt")).unwrap()),
+            vec![String::from("admin")],
+        );
+        moderation.mute("alice", None);
+        moderation.mute("bob", Some(Duration::ZERO));
+
+        assert!(moderation.is_muted("alice"));
+        assert!(!moderation.is_muted("bob"));
+        assert!(moderation.is_operator("admin"));
+        assert!(!moderation.is_operator("alice"));
+    }
+}
@@ -0,0 +1,266 @@
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ws::line_file::{LineFile, Saving};
+use crate::ws::moderation::now;
+
+// Keeps a full mailbox from growing the file without bounds.
+pub const MAX_PENDING_PER_NICK: usize = 100;
+
+// Private message waiting for its receiver to log in.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PendingMessage {
+    pub id: u64,
+    pub receiver: String,
+    pub sender: String,
+    // Unix time.
+    pub sent_at: u64,
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum QueueError {
+    Full,
+    Storage(String),
+}
+
+impl std::fmt::Display for QueueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Full => write!(
+                f,
+                "too many pending messages, at most {}",
+                MAX_PENDING_PER_NICK
+            ),
+            Self::Storage(e) => write!(f, "could not store message: {}", e),
+        }
+    }
+}
+
+// Keeps one JSON encoded message per line, oldest first. Messages older than `ttl` are
+// dropped undelivered.
+pub struct OfflineQueue {
+    file: LineFile,
+    ttl: Duration,
+    messages: Mutex<Vec<PendingMessage>>,
+}
+
+impl OfflineQueue {
+    pub fn new(path: &Path, ttl: Duration) -> std::io::Result<Self> {
+        let (file, messages) = LineFile::open(path, "pending private messages", |line| {
+            serde_json::from_str(line).ok()
+        })?;
+
+        Ok(Self {
+            file,
+            ttl,
+            messages: Mutex::new(messages),
+        })
+    }
+
+    pub fn push(
+        &self,
+        id: u64,
+        receiver: &str,
+        sender: &str,
+        message: &str,
+    ) -> Result<(), QueueError> {
+        let saving = self.file.saving();
+        let content = {
+            let mut messages = self.lock();
+            let pending = messages
+                .iter()
+                .filter(|pending| pending.receiver == receiver)
+                .count();
+            if pending >= MAX_PENDING_PER_NICK {
+                return Err(QueueError::Full);
+            }
+
+            messages.push(PendingMessage {
+                id,
+                receiver: receiver.to_string(),
+                sender: sender.to_string(),
+                sent_at: now(),
+                message: message.to_string(),
+            });
+            render(&messages)
+        };
+
+        if let Err(e) = save(&saving, content) {
+            self.lock().retain(|pending| pending.id != id);
+            return Err(QueueError::Storage(e.to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn list(&self, receiver: &str) -> Vec<PendingMessage> {
+        self.lock()
+            .iter()
+            .filter(|pending| pending.receiver == receiver)
+            .cloned()
+            .collect()
+    }
+
+    // Removes the messages of `receiver` and returns them oldest first.
+    pub fn take(&self, receiver: &str) -> Vec<PendingMessage> {
+        let saving = self.file.saving();
+        let (taken, content) = {
+            let mut messages = self.lock();
+            let (taken, kept): (Vec<_>, Vec<_>) = messages
+                .drain(..)
+                .partition(|pending| pending.receiver == receiver);
+            *messages = kept;
+            let content = (!taken.is_empty()).then(|| render(&messages));
+            (taken, content)
+        };
+
+        if let Some(Err(e)) = content.map(|content| save(&saving, content)) {
+            eprintln!("Could not store pending messages, error: {}", e);
+        }
+        taken
+    }
+
+    pub fn clear(&self, receiver: &str) -> usize {
+        self.take(receiver).len()
+    }
+
+    // Replaces the text of a message that was not delivered yet, false if there is none.
+    pub fn edit(&self, id: u64, message: &str) -> Result<bool, QueueError> {
+        let saving = self.file.saving();
+        let content = {
+            let mut messages = self.lock();
+            let Some(pending) = messages.iter_mut().find(|pending| pending.id == id) else {
+                return Ok(false);
+            };
+            pending.message = message.to_string();
+            render(&messages)
+        };
+
+        save(&saving, content)
+            .map(|()| true)
+            .map_err(|e| QueueError::Storage(e.to_string()))
+    }
+
+    pub fn remove(&self, id: u64) -> Result<bool, QueueError> {
+        let saving = self.file.saving();
+        let content = {
+            let mut messages = self.lock();
+            let pending = messages.len();
+            messages.retain(|message| message.id != id);
+            if messages.len() == pending {
+                return Ok(false);
+            }
+            render(&messages)
+        };
+
+        save(&saving, content)
+            .map(|()| true)
+            .map_err(|e| QueueError::Storage(e.to_string()))
+    }
+
+    // Locks the queue after dropping expired messages.
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<PendingMessage>> {
+        let mut messages = self.messages.lock().unwrap();
+        let oldest = now().saturating_sub(self.ttl.as_secs());
+        messages.retain(|pending| pending.sent_at > oldest);
+        messages
+    }
+}
+
+fn save(saving: &Saving, content: serde_json::Result<String>) -> std::io::Result<()> {
+    saving.write(&content?)
+}
+
+fn render(messages: &[PendingMessage]) -> serde_json::Result<String> {
+    let mut content = String::new();
+    for message in messages {
+        content.push_str(&serde_json::to_string(message)?);
+        content.push('\n');
+    }
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::test_util::TempDir;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    #[test]
+    fn test_messages_are_persisted_in_order() {
+        let dir = TempDir::new("queue");
+        let path = dir.join("messages.txt");
+        let queue = OfflineQueue::new(&path, DAY).unwrap();
+        queue.push(1, "bob", "alice", "first").unwrap();
+        queue.push(2, "carol", "alice", "other").unwrap();
+        queue.push(3, "bob", "dave", "second\nline").unwrap();
+
+        let queue = OfflineQueue::new(&path, DAY).unwrap();
+        let messages: Vec<String> = queue
+            .take("bob")
+            .into_iter()
+            .map(|pending| pending.message)
+            .collect();
+
+        assert_eq!(messages, vec!["first", "second\nline"]);
+        assert!(queue.take("bob").is_empty());
+        assert_eq!(
+            OfflineQueue::new(&path, DAY).unwrap().list("carol").len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_list_and_clear() {
+        let dir = TempDir::new("clear");
+        let queue = OfflineQueue::new(&dir.join("messages.txt"), DAY).unwrap();
+        queue.push(1, "bob", "alice", "hi").unwrap();
+
+        // Listing keeps the messages.
+        assert_eq!(queue.list("bob").len(), 1);
+        assert_eq!(queue.clear("bob"), 1);
+        assert!(queue.list("bob").is_empty());
+    }
+
+    #[test]
+    fn test_edit_and_remove_pending() {
+        let dir = TempDir::new("edit");
+        let path = dir.join("messages.txt");
+        let queue = OfflineQueue::new(&path, DAY).unwrap();
+        queue.push(1, "bob", "alice", "helo").unwrap();
+        queue.push(2, "bob", "alice", "oops").unwrap();
+
+        assert_eq!(queue.edit(1, "hello"), Ok(true));
+        assert_eq!(queue.remove(2), Ok(true));
+        assert_eq!(queue.edit(3, "missing"), Ok(false));
+
+        let pending = OfflineQueue::new(&path, DAY).unwrap().take("bob");
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].message, "hello");
+    }
+
+    #[test]
+    fn test_messages_expire() {
+        let dir = TempDir::new("expire");
+        let queue = OfflineQueue::new(&dir.join("messages.txt"), Duration::ZERO).unwrap();
+        queue.push(1, "bob", "alice", "hi").unwrap();
+
+        assert!(queue.take("bob").is_empty());
+    }
+
+    #[test]
+    fn test_mailbox_is_bounded() {
+        let dir = TempDir::new("full");
+        let queue = OfflineQueue::new(&dir.join("messages.txt"), DAY).unwrap();
+        for id in 0..MAX_PENDING_PER_NICK as u64 {
+            queue.push(id, "bob", "alice", "hi").unwrap();
+        }
+
+        assert_eq!(queue.push(100, "bob", "alice", "hi"), Err(QueueError::Full));
+        assert_eq!(queue.push(101, "carol", "alice", "hi"), Ok(()));
+    }
+}
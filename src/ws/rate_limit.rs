@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Addresses an IpRateLimiter keeps buckets for, refused addresses beyond it wait until
+// the buckets of others refill.
+const MAX_TRACKED_IPS: usize = 10_000;
+
+// Allows `burst` messages at once, refilled at `per_second` messages per second.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+impl RateLimit {
+    pub const fn new(burst: u32, per_second: f64) -> Self {
+        Self { burst, per_second }
+    }
+
+    // Parses "<burst>/<per second>", e.g. "10/2".
+    pub fn parse(limit: &str) -> Option<Self> {
+        let (burst, per_second) = limit.split_once('/')?;
+        let burst = burst.trim().parse().ok()?;
+        let per_second: f64 = per_second.trim().parse().ok()?;
+        if burst == 0 || !per_second.is_finite() || per_second <= 0.0 {
+            return None;
+        }
+        Some(Self::new(burst, per_second))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimits {
+    pub chat: RateLimit,
+    pub private: RateLimit,
+    pub command: RateLimit,
+    // Typing notifications, the ones over the limit are dropped without a warning.
+    pub typing: RateLimit,
+    // Chat and private messages of all sessions together.
+    pub global: RateLimit,
+    // Messages over the limit that only get a warning, the next one mutes.
+    pub warnings: u32,
+    pub mute_duration: Duration,
+    // Going over the limit after the mute disconnects, unless the session behaved
+    // for this long.
+    pub strike_reset: Duration,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            chat: RateLimit::new(5, 1.0),
+            private: RateLimit::new(5, 1.0),
+            command: RateLimit::new(10, 2.0),
+            typing: RateLimit::new(5, 1.0),
+            global: RateLimit::new(200, 100.0),
+            warnings: 2,
+            mute_duration: Duration::from_secs(30),
+            strike_reset: Duration::from_secs(60),
+        }
+    }
+}
+
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    pub fn try_take(&mut self) -> bool {
+        self.try_take_at(Instant::now())
+    }
+
+    fn try_take_at(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst as f64);
+        self.updated = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    // A full bucket behaves like a new one.
+    fn is_full_at(&self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens + elapsed * self.limit.per_second >= self.limit.burst as f64
+    }
+}
+
+// One token bucket per client address, for requests made outside of a chat session.
+pub struct IpRateLimiter {
+    limit: RateLimit,
+    buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+}
+
+impl IpRateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn try_take(&self, ip: IpAddr) -> bool {
+        self.try_take_at(ip, Instant::now())
+    }
+
+    fn try_take_at(&self, ip: IpAddr, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_IPS && !buckets.contains_key(&ip) {
+            buckets.retain(|_, bucket| !bucket.is_full_at(now));
+            if buckets.len() >= MAX_TRACKED_IPS {
+                return false;
+            }
+        }
+
+        buckets
+            .entry(ip)
+            .or_insert_with(|| TokenBucket {
+                updated: now,
+                ..TokenBucket::new(self.limit)
+            })
+            .try_take_at(now)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageKind {
+    Chat,
+    Private,
+    Command,
+    Typing,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    // Harmless message over the limit, dropped silently.
+    Ignore,
+    Warn,
+    Mute(Duration),
+    Disconnect,
+}
+
+// Rate limits of one session, escalating from warnings to a mute to disconnecting
+// when the client keeps going over them.
+pub struct FloodGuard {
+    chat: TokenBucket,
+    private: TokenBucket,
+    command: TokenBucket,
+    typing: TokenBucket,
+    limits: RateLimits,
+    strikes: u32,
+    last_strike: Option<Instant>,
+}
+
+impl FloodGuard {
+    pub fn new(limits: &RateLimits) -> Self {
+        Self {
+            chat: TokenBucket::new(limits.chat),
+            private: TokenBucket::new(limits.private),
+            command: TokenBucket::new(limits.command),
+            typing: TokenBucket::new(limits.typing),
+            limits: *limits,
+            strikes: 0,
+            last_strike: None,
+        }
+    }
+
+    pub fn check(&mut self, kind: MessageKind) -> Verdict {
+        self.check_at(kind, Instant::now())
+    }
+
+    fn check_at(&mut self, kind: MessageKind, now: Instant) -> Verdict {
+        let bucket = match kind {
+            MessageKind::Chat => &mut self.chat,
+            MessageKind::Private => &mut self.private,
+            MessageKind::Command => &mut self.command,
+            MessageKind::Typing => &mut self.typing,
+        };
+        if bucket.try_take_at(now) {
+            return Verdict::Allow;
+        }
+        if kind == MessageKind::Typing {
+            return Verdict::Ignore;
+        }
+
+        if self
+            .last_strike
+            .is_some_and(|last| now.saturating_duration_since(last) >= self.limits.strike_reset)
+        {
+            self.strikes = 0;
+        }
+        self.strikes += 1;
+        self.last_strike = Some(now);
+
+        match self.strikes {
+            strikes if strikes <= self.limits.warnings => Verdict::Warn,
+            strikes if strikes == self.limits.warnings + 1 => {
+                Verdict::Mute(self.limits.mute_duration)
+            }
+            _ => Verdict::Disconnect,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate_limit() {
+        assert_eq!(RateLimit::parse("10/2"), Some(RateLimit::new(10, 2.0)));
+        assert_eq!(RateLimit::parse("3/0.5"), Some(RateLimit::new(3, 0.5)));
+        assert_eq!(RateLimit::parse("0/1"), None);
+        assert_eq!(RateLimit::parse("10/0"), None);
+        assert_eq!(RateLimit::parse("10"), None);
+    }
+
+    #[test]
+    fn test_token_bucket_refills() {
+        let mut bucket = TokenBucket::new(RateLimit::new(2, 1.0));
+        let start = bucket.updated;
+
+        assert!(bucket.try_take_at(start));
+        assert!(bucket.try_take_at(start));
+        assert!(!bucket.try_take_at(start));
+        assert!(!bucket.try_take_at(start + Duration::from_millis(500)));
+        assert!(bucket.try_take_at(start + Duration::from_secs(1)));
+        assert!(bucket.try_take_at(start + Duration::from_secs(10)));
+        assert!(bucket.try_take_at(start + Duration::from_secs(10)));
+        assert!(!bucket.try_take_at(start + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_ip_rate_limiter() {
+        let limiter = IpRateLimiter::new(RateLimit::new(2, 1.0));
+        let alice = IpAddr::from([10, 0, 0, 1]);
+        let bob = IpAddr::from([10, 0, 0, 2]);
+        let now = Instant::now();
+
+        assert!(limiter.try_take_at(alice, now));
+        assert!(limiter.try_take_at(alice, now));
+        assert!(!limiter.try_take_at(alice, now));
+        assert!(limiter.try_take_at(bob, now));
+        assert!(limiter.try_take_at(alice, now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_flood_guard_escalates() {
+        let limits = RateLimits {
+            chat: RateLimit::new(1, 0.001),
+            ..RateLimits::default()
+        };
+        let mut guard = FloodGuard::new(&limits);
+        let now = Instant::now();
+
+        assert_eq!(guard.check_at(MessageKind::Chat, now), Verdict::Allow);
+        assert_eq!(guard.check_at(MessageKind::Command, now), Verdict::Allow);
+        assert_eq!(guard.check_at(MessageKind::Chat, now), Verdict::Warn);
+        assert_eq!(guard.check_at(MessageKind::Chat, now), Verdict::Warn);
+        assert_eq!(
+            guard.check_at(MessageKind::Chat, now),
+            Verdict::Mute(limits.mute_duration)
+        );
+        assert_eq!(guard.check_at(MessageKind::Chat, now), Verdict::Disconnect);
+    }
+
+    #[test]
+    fn test_flood_guard_ignores_typing() {
+        let limits = RateLimits {
+            typing: RateLimit::new(1, 0.001),
+            ..RateLimits::default()
+        };
+        let mut guard = FloodGuard::new(&limits);
+        let now = Instant::now();
+
+        assert_eq!(guard.check_at(MessageKind::Typing, now), Verdict::Allow);
+        for _ in 0..=limits.warnings + 1 {
+            assert_eq!(guard.check_at(MessageKind::Typing, now), Verdict::Ignore);
+        }
+        assert_eq!(guard.check_at(MessageKind::Chat, now), Verdict::Allow);
+    }
+
+    #[test]
+    fn test_flood_guard_forgets_old_strikes() {
+        let limits = RateLimits {
+            chat: RateLimit::new(1, 0.001),
+            ..RateLimits::default()
+        };
+        let mut guard = FloodGuard::new(&limits);
+        let now = Instant::now();
+
+        guard.check_at(MessageKind::Chat, now);
+        guard.check_at(MessageKind::Chat, now);
+        guard.check_at(MessageKind::Chat, now);
+        let later = now + limits.strike_reset;
+
+        assert_eq!(guard.check_at(MessageKind::Chat, later), Verdict::Warn);
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::ws::identity::Identity;
+use crate::ws::moderation::now;
+
+// Oldest unread messages are forgotten past this, their read receipts are not sent.
+pub const MAX_UNREAD: usize = 10_000;
+
+// Hands out message ids, unique across restarts as they start from the current time in
+// milliseconds.
+pub struct MessageIds {
+    next: AtomicU64,
+}
+
+impl MessageIds {
+    pub fn new() -> Self {
+        Self {
+            next: AtomicU64::new(now() * 1000),
+        }
+    }
+
+    pub fn next(&self) -> u64 {
+        self.next.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+impl Default for MessageIds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Unread {
+    sender: String,
+    // The receiving nickname could be taken by someone else before it is read.
+    reader: Identity,
+}
+
+#[derive(Default)]
+struct UnreadMessages {
+    messages: HashMap<u64, Unread>,
+    // Ids in delivery order, to forget the oldest ones.
+    order: VecDeque<u64>,
+}
+
+// Private messages delivered but not read yet, so the read receipt finds its sender.
+#[derive(Default)]
+pub struct ReadReceipts {
+    unread: Mutex<UnreadMessages>,
+}
+
+impl ReadReceipts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn delivered(&self, id: u64, sender: &str, reader: Identity) {
+        let mut unread = self.unread.lock().unwrap();
+        while unread.order.len() >= MAX_UNREAD {
+            if let Some(oldest) = unread.order.pop_front() {
+                unread.messages.remove(&oldest);
+            }
+        }
+
+        unread.order.push_back(id);
+        unread.messages.insert(
+            id,
+            Unread {
+                sender: sender.to_string(),
+                reader,
+            },
+        );
+    }
+
+    // Returns the sender to notify, only the first time the receiver reads the message.
+    pub fn read(&self, id: u64, reader: &Identity) -> Option<String> {
+        let mut unread = self.unread.lock().unwrap();
+        if unread.messages.get(&id)?.reader != *reader {
+            return None;
+        }
+
+        unread.order.retain(|unread_id| *unread_id != id);
+        unread.messages.remove(&id).map(|message| message.sender)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_receipt_once_by_receiver() {
+        let receipts = ReadReceipts::new();
+        let bob = Identity::Account(String::from("bob"));
+        receipts.delivered(1, "alice", bob.clone());
+
+        assert_eq!(
+            receipts.read(1, &Identity::Account(String::from("alice"))),
+            None
+        );
+        assert_eq!(receipts.read(1, &Identity::guest()), None);
+        assert_eq!(receipts.read(1, &bob), Some(String::from("alice")));
+        assert_eq!(receipts.read(1, &bob), None);
+        assert_eq!(receipts.read(2, &bob), None);
+    }
+
+    #[test]
+    fn test_oldest_unread_are_forgotten() {
+        let receipts = ReadReceipts::new();
+        let bob = Identity::guest();
+        for id in 0..=MAX_UNREAD as u64 {
+            receipts.delivered(id, "alice", bob.clone());
+        }
+
+        assert_eq!(receipts.read(0, &bob), None);
+        assert_eq!(receipts.read(1, &bob), Some(String::from("alice")));
+    }
+
+    #[test]
+    fn test_message_ids_increase() {
+        let ids = MessageIds::new();
+        let first = ids.next();
+
+        assert!(ids.next() > first);
+    }
+}
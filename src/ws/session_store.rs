@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+pub const SESSION_COOKIE: &str = "chat_session";
+
+struct Session {
+    nick: String,
+    expires_at: Instant,
+}
+
+// Logged in browser sessions, keyed by the random id stored in the session cookie.
+pub struct SessionStore {
+    sessions: Mutex<HashMap<String, Session>>,
+    ttl: Duration,
+}
+
+impl SessionStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn create(&self, nick: &str) -> String {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let id = URL_SAFE_NO_PAD.encode(bytes);
+
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(
+            id.clone(),
+            Session {
+                nick: nick.to_string(),
+                expires_at: now + self.ttl,
+            },
+        );
+        id
+    }
+
+    pub fn get(&self, id: &str) -> Option<String> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get(id) {
+            Some(session) if session.expires_at > Instant::now() => Some(session.nick.clone()),
+            Some(_) => {
+                sessions.remove(id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn remove(&self, id: &str) {
+        self.sessions.lock().unwrap().remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_get_remove() {
+        let store = SessionStore::new(Duration::from_secs(60));
+        let id = store.create("alice");
+
+        assert_eq!(store.get(&id), Some(String::from("alice")));
+        assert_ne!(store.create("alice"), id);
+
+        store.remove(&id);
+        assert_eq!(store.get(&id), None);
+    }
+
+    #[test]
+    fn test_expired_session() {
+        let store = SessionStore::new(Duration::ZERO);
+        let id = store.create("alice");
+
+        assert_eq!(store.get(&id), None);
+    }
+}
@@ -1,8 +1,10 @@
 use std::sync::Arc;
 
+use crate::ws::compression::{encoded_etag, is_compressible, negotiate};
 use crate::ws::file_storage::FileStorage;
 use crate::ws::handler::Handler;
 use crate::ws::http_header::HttpHeader;
+use crate::ws::http_range::{multipart_byteranges, parse_range_header, RangeError};
 use crate::ws::http_request::HttpRequest;
 use crate::ws::http_response::{HttpResponse, StatusType};
 
@@ -12,25 +14,97 @@ pub struct StaticFileHandler {
 }
 
 impl Handler for StaticFileHandler {
-    fn handle(&self, _request: &HttpRequest, response: &mut HttpResponse) {
+    fn handle(&self, request: &HttpRequest, response: &mut HttpResponse) {
         let file_content = if let Some(file_content) = self.file_storage.get(&self.file_name) {
             file_content
         } else {
-            todo!("Response Not found");
+            eprintln!("Static file {} is not loaded", self.file_name);
+            *response = HttpResponse::new(StatusType::NotFound, Vec::new(), Vec::new());
+            return;
         };
 
-        let mut headers = Vec::new();
-        headers.push(HttpHeader::new(
-            "Content-Type",
-            extension_to_http_mimo_type(
-                &self.file_name[self
-                    .file_name
-                    .find('.')
-                    .expect("File should have extension")..],
-            ),
-        ));
+        let content_type = self
+            .file_storage
+            .content_type(&self.file_name)
+            .unwrap_or_default();
+        let etag = self.file_storage.etag(&self.file_name).unwrap_or_default();
 
-        *response = HttpResponse::new(StatusType::Ok, headers, file_content.to_vec());
+        let mut headers = vec![HttpHeader::new("Accept-Ranges", "bytes")];
+
+        // Already compressed formats like image/png are always sent as they are.
+        let available = if is_compressible(content_type) {
+            headers.push(HttpHeader::new("Vary", "Accept-Encoding"));
+            self.file_storage.encodings(&self.file_name)
+        } else {
+            Vec::new()
+        };
+
+        // Ranges always address the identity representation, a multipart body can't be
+        // sent under a Content-Encoding that only its parts were cut from.
+        let range = request
+            .header("Range")
+            .filter(|_| if_range_matches(request, etag));
+
+        match range.map(|range| parse_range_header(range, file_content.len())) {
+            None | Some(Err(RangeError::Malformed)) => {
+                let encoded = request
+                    .header("Accept-Encoding")
+                    .and_then(|value| negotiate(value, &available))
+                    .and_then(|coding| {
+                        self.file_storage
+                            .get_encoded(&self.file_name, coding)
+                            .map(|content| (coding, content))
+                    });
+                let content = match encoded {
+                    Some((coding, content)) => {
+                        headers.push(HttpHeader::new("ETag", &encoded_etag(etag, coding)));
+                        headers.push(HttpHeader::new("Content-Encoding", coding.name()));
+                        content
+                    }
+                    None => {
+                        headers.push(HttpHeader::new("ETag", etag));
+                        file_content
+                    }
+                };
+                headers.push(HttpHeader::new("Content-Type", content_type));
+                *response = HttpResponse::new(StatusType::Ok, headers, content.to_vec());
+            }
+            Some(Err(RangeError::Unsatisfiable)) => {
+                headers.push(HttpHeader::new("ETag", etag));
+                headers.push(HttpHeader::new(
+                    "Content-Range",
+                    &format!("bytes */{}", file_content.len()),
+                ));
+                *response = HttpResponse::new(StatusType::RangeNotSatisfiable, headers, Vec::new());
+            }
+            Some(Ok(ranges)) if ranges.len() == 1 => {
+                let range = ranges[0];
+                headers.push(HttpHeader::new("ETag", etag));
+                headers.push(HttpHeader::new("Content-Type", content_type));
+                headers.push(HttpHeader::new(
+                    "Content-Range",
+                    &range.content_range(file_content.len()),
+                ));
+                *response = HttpResponse::new(
+                    StatusType::PartialContent,
+                    headers,
+                    file_content[range.start..=range.end].to_vec(),
+                );
+            }
+            Some(Ok(ranges)) => {
+                let boundary = format!("byteranges-{}", etag.trim_matches('"'));
+                headers.push(HttpHeader::new("ETag", etag));
+                headers.push(HttpHeader::new(
+                    "Content-Type",
+                    &format!("multipart/byteranges; boundary={}", boundary),
+                ));
+                *response = HttpResponse::new(
+                    StatusType::PartialContent,
+                    headers,
+                    multipart_byteranges(file_content, &ranges, content_type, &boundary),
+                );
+            }
+        }
     }
 }
 
@@ -43,14 +117,139 @@ impl StaticFileHandler {
     }
 }
 
-fn extension_to_http_mimo_type(extension: &str) -> &str {
-    match extension {
-        ".html" => "text/html",
-        ".css" => "text/css",
-        ".png" => "image/png",
-        ".js" => "text/javascript",
-        _ => {
-            todo!("Not supported mimo type");
-        }
+// A Range request is only honored when If-Range is absent or carries the current
+// entity tag. Dates are not tracked for stored files, so they never match.
+fn if_range_matches(request: &HttpRequest, etag: &str) -> bool {
+    match request.header("If-Range") {
+        Some(value) => value.trim() == etag,
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::compression::ContentCoding;
+    use std::path::Path;
+
+    fn handler() -> StaticFileHandler {
+        let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+        let mut file_storage = FileStorage::new(&assets).unwrap();
+        file_storage.precompress();
+        StaticFileHandler::new(Arc::new(file_storage), String::from("script.js"))
+    }
+
+    fn request(headers: &[(&str, &str)]) -> HttpRequest {
+        let mut request = HttpRequest::default();
+        request.method = String::from("GET");
+        request.uri = String::from("/script.js");
+        request.headers = headers
+            .iter()
+            .map(|(name, value)| HttpHeader::new(name, value))
+            .collect();
+        request
+    }
+
+    fn handle(handler: &StaticFileHandler, headers: &[(&str, &str)]) -> HttpResponse {
+        let mut response = HttpResponse::default();
+        handler.handle(&request(headers), &mut response);
+        response
+    }
+
+    #[test]
+    fn test_full_response_is_encoded() {
+        let handler = handler();
+        let etag = handler.file_storage.etag("script.js").unwrap().to_string();
+
+        let response = handle(&handler, &[("Accept-Encoding", "gzip")]);
+
+        assert_eq!(response.status, StatusType::Ok);
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(
+            response.header("ETag"),
+            Some(encoded_etag(&etag, ContentCoding::Gzip).as_str())
+        );
+    }
+
+    #[test]
+    fn test_ranges_use_identity_representation() {
+        let handler = handler();
+        let content = handler.file_storage.get("script.js").unwrap().clone();
+        let etag = handler.file_storage.etag("script.js").unwrap().to_string();
+
+        let response = handle(
+            &handler,
+            &[("Accept-Encoding", "gzip"), ("Range", "bytes=0-1")],
+        );
+
+        assert_eq!(response.status, StatusType::PartialContent);
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.header("ETag"), Some(etag.as_str()));
+        assert_eq!(response.body, content[0..=1]);
+    }
+
+    #[test]
+    fn test_multipart_ranges_are_not_encoded() {
+        let handler = handler();
+        let content = handler.file_storage.get("script.js").unwrap().clone();
+
+        let response = handle(
+            &handler,
+            &[("Accept-Encoding", "gzip"), ("Range", "bytes=0-1,3-4")],
+        );
+
+        assert_eq!(response.status, StatusType::PartialContent);
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert!(response
+            .header("Content-Type")
+            .unwrap()
+            .starts_with("multipart/byteranges"));
+        let body = String::from_utf8_lossy(&response.body);
+        assert!(body.contains(&format!("bytes 0-1/{}", content.len())));
+        assert!(body.contains(&format!("bytes 3-4/{}", content.len())));
+        assert!(response.body.windows(2).any(|part| part == &content[3..=4]));
+    }
+
+    #[test]
+    fn test_overlapping_ranges_are_sent_once() {
+        let handler = handler();
+        let content = handler.file_storage.get("script.js").unwrap().clone();
+
+        let response = handle(&handler, &[("Range", "bytes=0-,0-,0-")]);
+
+        assert_eq!(response.status, StatusType::PartialContent);
+        assert_eq!(
+            response.header("Content-Range"),
+            Some(format!("bytes 0-{}/{}", content.len() - 1, content.len()).as_str())
+        );
+        assert_eq!(response.body, *content);
+    }
+
+    #[test]
+    fn test_if_range_with_current_etag() {
+        let handler = handler();
+        let content = handler.file_storage.get("script.js").unwrap().clone();
+        let etag = handler.file_storage.etag("script.js").unwrap().to_string();
+
+        let response = handle(&handler, &[("Range", "bytes=0-1"), ("If-Range", &etag)]);
+
+        assert_eq!(response.status, StatusType::PartialContent);
+        assert_eq!(response.body, content[0..=1]);
+    }
+
+    #[test]
+    fn test_if_range_with_stale_etag() {
+        let handler = handler();
+        let content = handler.file_storage.get("script.js").unwrap().clone();
+
+        let response = handle(
+            &handler,
+            &[("Range", "bytes=0-1"), ("If-Range", "\"stale\"")],
+        );
+
+        assert_eq!(response.status, StatusType::Ok);
+        assert_eq!(response.header("Content-Range"), None);
+        assert_eq!(response.body, *content);
     }
 }
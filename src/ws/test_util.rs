@@ -0,0 +1,26 @@
+use std::fs::remove_dir_all;
+use std::path::PathBuf;
+
+// Directory of one test under the system temp dir, removed again when dropped.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    // `name` has to be unique among the tests, they run in parallel.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("ws_chat_{}_{}", name, std::process::id()));
+        let _ = remove_dir_all(&path);
+        Self { path }
+    }
+
+    pub fn join(&self, file: &str) -> PathBuf {
+        self.path.join(file)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,216 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::ws::http_request::HttpRequest;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Name of the query parameter and cookie carrying the token.
+pub const TOKEN_PARAM: &str = "access_token";
+// Prefix of a Sec-WebSocket-Protocol entry carrying the token, e.g. "access_token.<jwt>".
+pub const TOKEN_PROTOCOL_PREFIX: &str = "access_token.";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TokenError {
+    Malformed,
+    UnsupportedAlgorithm,
+    InvalidSignature,
+    Expired,
+    NotYetValid,
+}
+
+impl std::fmt::Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "malformed token"),
+            Self::UnsupportedAlgorithm => write!(f, "unsupported algorithm"),
+            Self::InvalidSignature => write!(f, "invalid signature"),
+            Self::Expired => write!(f, "token expired"),
+            Self::NotYetValid => write!(f, "token not yet valid"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    typ: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<u64>,
+}
+
+// Validates HS256 signed JWTs issued by the application that embeds the chat.
+#[derive(Clone)]
+pub struct TokenValidator {
+    secret: Vec<u8>,
+}
+
+impl TokenValidator {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.to_vec(),
+        }
+    }
+
+    // Tokens are issued by the embedding application, this is used to test validation.
+    #[cfg(test)]
+    pub fn issue(&self, subject: &str, ttl_secs: u64) -> String {
+        let header = Header {
+            alg: String::from("HS256"),
+            typ: Some(String::from("JWT")),
+        };
+        let claims = Claims {
+            sub: subject.to_string(),
+            exp: now() + ttl_secs,
+            nbf: None,
+        };
+
+        let signing_input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header).unwrap()),
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap())
+        );
+        let signature = URL_SAFE_NO_PAD.encode(self.sign(signing_input.as_bytes()));
+
+        format!("{}.{}", signing_input, signature)
+    }
+
+    pub fn validate(&self, token: &str) -> Result<Claims, TokenError> {
+        let (signing_input, signature) = token.rsplit_once('.').ok_or(TokenError::Malformed)?;
+        let (header, claims) = signing_input.split_once('.').ok_or(TokenError::Malformed)?;
+
+        let header: Header = decode_part(header)?;
+        if header.alg != "HS256" {
+            return Err(TokenError::UnsupportedAlgorithm);
+        }
+
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| TokenError::Malformed)?;
+        let mut mac = self.mac();
+        mac.update(signing_input.as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| TokenError::InvalidSignature)?;
+
+        let claims: Claims = decode_part(claims)?;
+        let now = now();
+        if claims.exp <= now {
+            return Err(TokenError::Expired);
+        }
+        if claims.nbf.is_some_and(|nbf| nbf > now) {
+            return Err(TokenError::NotYetValid);
+        }
+
+        Ok(claims)
+    }
+
+    #[cfg(test)]
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+        let mut mac = self.mac();
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any size")
+    }
+}
+
+// Looks for a token in the query string, the cookie and the offered subprotocols, in that order.
+pub fn find_token(request: &HttpRequest) -> Option<&str> {
+    request
+        .query_param(TOKEN_PARAM)
+        .or_else(|| request.cookie(TOKEN_PARAM))
+        .or_else(|| {
+            request
+                .header("Sec-WebSocket-Protocol")?
+                .split(',')
+                .map(str::trim)
+                .find_map(|protocol| protocol.strip_prefix(TOKEN_PROTOCOL_PREFIX))
+        })
+}
+
+fn decode_part<T: for<'de> Deserialize<'de>>(part: &str) -> Result<T, TokenError> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(part)
+        .map_err(|_| TokenError::Malformed)?;
+    serde_json::from_slice(&bytes).map_err(|_| TokenError::Malformed)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::http_header::HttpHeader;
+
+    #[test]
+    fn test_issue_and_validate() {
+        let validator = TokenValidator::new(b"secret");
+        let token = validator.issue("alice", 60);
+
+        let claims = validator.validate(&token).unwrap();
+
+        assert_eq!(claims.sub, "alice");
+    }
+
+    #[test]
+    fn test_reject_tampered_and_foreign_tokens() {
+        let validator = TokenValidator::new(b"secret");
+        let token = TokenValidator::new(b"other secret").issue("alice", 60);
+
+        assert_eq!(
+            validator.validate(&token),
+            Err(TokenError::InvalidSignature)
+        );
+        assert_eq!(
+            validator.validate("not.a-token"),
+            Err(TokenError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_reject_expired_token() {
+        let validator = TokenValidator::new(b"secret");
+        let token = validator.issue("alice", 0);
+
+        assert_eq!(validator.validate(&token), Err(TokenError::Expired));
+    }
+
+    #[test]
+    fn test_find_token() {
+        let mut request = HttpRequest::default();
+        request.uri = String::from("/chat?room=main&access_token=abc.def.ghi");
+        assert_eq!(find_token(&request), Some("abc.def.ghi"));
+
+        request.uri = String::from("/chat");
+        request.headers = vec![HttpHeader::new(
+            "Sec-WebSocket-Protocol",
+            "chat.v1.json, access_token.abc.def.ghi",
+        )];
+        assert_eq!(find_token(&request), Some("abc.def.ghi"));
+
+        request.headers = vec![HttpHeader::new(
+            "Cookie",
+            "theme=dark; access_token=abc.def.ghi",
+        )];
+        assert_eq!(find_token(&request), Some("abc.def.ghi"));
+    }
+}
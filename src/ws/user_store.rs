@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use rand::rngs::OsRng;
+
+use crate::ws::line_file::LineFile;
+
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum UserStoreError {
+    AlreadyRegistered,
+    PasswordTooShort,
+    Storage(String),
+}
+
+impl std::fmt::Display for UserStoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AlreadyRegistered => write!(f, "nickname is already registered"),
+            Self::PasswordTooShort => write!(
+                f,
+                "password must have at least {} characters",
+                MIN_PASSWORD_LEN
+            ),
+            Self::Storage(e) => write!(f, "could not store account: {}", e),
+        }
+    }
+}
+
+// Registered accounts. Implementations are called from blocking threads, password
+// hashing is expensive on purpose.
+pub trait UserStore {
+    fn is_registered(&self, nick: &str) -> bool;
+    fn register(&self, nick: &str, password: &str) -> Result<(), UserStoreError>;
+    fn verify(&self, nick: &str, password: &str) -> bool;
+}
+
+// Keeps one "<nick>:<argon2 PHC hash>" line per account.
+pub struct FileUserStore {
+    file: LineFile,
+    users: Mutex<HashMap<String, String>>,
+}
+
+impl FileUserStore {
+    pub fn new(path: &Path) -> std::io::Result<Self> {
+        let (file, users) = LineFile::open(path, "registered accounts", |line| {
+            let (nick, hash) = line.split_once(':')?;
+            Some((nick.to_string(), hash.to_string()))
+        })?;
+
+        Ok(Self {
+            file,
+            users: Mutex::new(users.into_iter().collect()),
+        })
+    }
+}
+
+impl UserStore for FileUserStore {
+    fn is_registered(&self, nick: &str) -> bool {
+        self.users.lock().unwrap().contains_key(nick)
+    }
+
+    fn register(&self, nick: &str, password: &str) -> Result<(), UserStoreError> {
+        if password.chars().count() < MIN_PASSWORD_LEN {
+            return Err(UserStoreError::PasswordTooShort);
+        }
+        if self.is_registered(nick) {
+            return Err(UserStoreError::AlreadyRegistered);
+        }
+
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| UserStoreError::Storage(e.to_string()))?
+            .to_string();
+
+        // Registrations take turns, lookups only wait for the insert below.
+        let saving = self.file.saving();
+        // Another session could register the same nick while we were hashing.
+        if self.is_registered(nick) {
+            return Err(UserStoreError::AlreadyRegistered);
+        }
+
+        saving
+            .append(&format!("{}:{}", nick, hash))
+            .map_err(|e| UserStoreError::Storage(e.to_string()))?;
+
+        self.users.lock().unwrap().insert(nick.to_string(), hash);
+        Ok(())
+    }
+
+    fn verify(&self, nick: &str, password: &str) -> bool {
+        let hash = match self.users.lock().unwrap().get(nick) {
+            Some(hash) => hash.clone(),
+            None => return false,
+        };
+
+        match PasswordHash::new(&hash) {
+            Ok(parsed) => Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok(),
+            Err(e) => {
+                eprintln!("Stored password hash of {} is invalid: {}", nick, e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::test_util::TempDir;
+
+    #[test]
+    fn test_register_and_verify() {
+        let dir = TempDir::new("register");
+        let path = dir.join("users.txt");
+        let store = FileUserStore::new(&path).unwrap();
+
+        store.register("alice", "correct horse").unwrap();
+
+        assert!(store.is_registered("alice"));
+        assert!(store.verify("alice", "correct horse"));
+        assert!(!store.verify("alice", "wrong password"));
+        assert!(!store.verify("bob", "correct horse"));
+        assert_eq!(
+            store.register("alice", "another password"),
+            Err(UserStoreError::AlreadyRegistered)
+        );
+        assert_eq!(
+            store.register("bob", "short"),
+            Err(UserStoreError::PasswordTooShort)
+        );
+    }
+
+    #[test]
+    fn test_accounts_are_persisted() {
+        let dir = TempDir::new("persist");
+        let path = dir.join("users.txt");
+        FileUserStore::new(&path)
+            .unwrap()
+            .register("alice", "correct horse")
+            .unwrap();
+
+        let store = FileUserStore::new(&path).unwrap();
+
+        assert!(store.verify("alice", "correct horse"));
+        assert!(!std::fs::read_to_string(&path)
+            .unwrap()
+            .contains("correct horse"));
+    }
+}
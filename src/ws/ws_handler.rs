@@ -0,0 +1,82 @@
+use futures::future::BoxFuture;
+use futures_util::{SinkExt, StreamExt};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Message, Role, WebSocketConfig};
+use tokio_tungstenite::WebSocketStream;
+
+use crate::ws::http_request::HttpRequest;
+
+pub type WsStream = WebSocketStream<TcpStream>;
+
+pub struct WsContext {
+    pub request: HttpRequest,
+    // Subprotocol selected during the handshake, None if the client offered none we speak.
+    pub protocol: Option<String>,
+    // Subject of a valid access token presented during the handshake.
+    pub user: Option<String>,
+}
+
+// Websocket counterpart of `Handler`. Called once the upgrade handshake for its route
+// completed, with the request that initiated it.
+pub trait WsHandler {
+    fn handle(&self, context: WsContext, socket: WsStream) -> BoxFuture<'static, ()>;
+
+    // Subprotocols offered during the handshake, the client's preference order wins.
+    fn protocols(&self) -> &[&'static str] {
+        &[]
+    }
+}
+
+// Accepted upgrade, waiting for the 101 response to be written before taking over the socket.
+pub struct WsUpgrade {
+    pub context: WsContext,
+    pub handler: Arc<dyn WsHandler + Send + Sync>,
+    // Bytes received after the handshake request, already part of the websocket stream.
+    pub leftover: Vec<u8>,
+}
+
+impl WsUpgrade {
+    pub async fn run(self, socket: TcpStream, config: WebSocketConfig) {
+        let ws_socket =
+            WebSocketStream::from_partially_read(socket, self.leftover, Role::Server, Some(config))
+                .await;
+        self.handler.handle(self.context, ws_socket).await;
+    }
+
+    // Closes the websocket right away instead of running the handler.
+    pub async fn reject(
+        self,
+        socket: TcpStream,
+        config: WebSocketConfig,
+        code: CloseCode,
+        reason: String,
+    ) {
+        let mut ws_socket =
+            WebSocketStream::from_partially_read(socket, self.leftover, Role::Server, Some(config))
+                .await;
+        let close_frame = CloseFrame {
+            code,
+            reason: reason.into(),
+        };
+        if ws_socket
+            .send(Message::Close(Some(close_frame)))
+            .await
+            .is_err()
+        {
+            return;
+        }
+
+        // Wait for the client to answer so it gets to read the reason.
+        let _ = tokio::time::timeout(Duration::from_secs(1), async {
+            while let Some(Ok(message)) = ws_socket.next().await {
+                if message.is_close() {
+                    break;
+                }
+            }
+        })
+        .await;
+    }
+}
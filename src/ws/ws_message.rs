@@ -1,38 +1,379 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct NickMessage {
     pub nick: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct PrivateMessage {
     pub receiver: String,
     pub message: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct ChatMessage {
     pub message: String,
+    // Id of a recent chat message this one answers.
+    #[serde(default)]
+    pub reply_to: Option<u64>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct RegisterMessage {
+    pub nick: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct LoginMessage {
+    pub nick: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct LogoutMessage {}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct OpMessage {
+    pub nick: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct KickMessage {
+    pub nick: String,
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct BanMessage {
+    // Nickname or IP address.
+    pub target: String,
+    pub duration: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct MuteMessage {
+    pub nick: String,
+    pub duration: Option<String>,
+}
+
+// Changes the text of a chat or private message of the user.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct EditMessage {
+    pub id: u64,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct DeleteMessage {
+    pub id: u64,
+}
+
+// Adds the reaction of the user to a chat or private message.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ReactMessage {
+    pub id: u64,
+    // An emoji or a shortcode like ":thumbsup:".
+    pub emoji: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct UnreactMessage {
+    pub id: u64,
+    pub emoji: String,
+}
+
+// Asks for the messages of the thread started by message `id`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ThreadMessage {
+    pub id: u64,
+}
+
+// Sent again every few seconds while the user types, to the chat or to the receiver of a
+// private message.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct TypingStartMessage {
+    #[serde(default)]
+    pub receiver: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct TypingStopMessage {}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Online,
+    Away,
+    // Do not disturb, mentions are not notified.
+    Dnd,
+}
+
+// Sets the status of this connection, with an optional text like "in a meeting".
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct StatusMessage {
+    pub status: Status,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+// Stops messages from `nick` reaching the user, without a nickname lists the ignored ones.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct IgnoreMessage {
+    #[serde(default)]
+    pub nick: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct UnignoreMessage {
+    pub nick: String,
+}
+
+// Lists the connected users with their status.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct UsersMessage {}
+
+// Marks private messages as read, their senders get a read receipt.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ReadMessage {
+    pub ids: Vec<u64>,
+}
+
+// Lists the private messages that wait for the user to log in.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct PendingMessagesMessage {}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct ClearPendingMessage {}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct HelpMessage {}
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct QuitMessage {}
 
-#[derive(Deserialize)]
+// Declares MessageType and its NAMES from one list, so the tags can't drift apart.
+macro_rules! message_types {
+    ($($name:literal => $variant:ident($message:ty),)*) => {
+        #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+        #[serde(tag = "message_type")]
+        pub enum MessageType {
+            $(
+                #[serde(rename = $name)]
+                $variant($message),
+            )*
+        }
+
+        impl MessageType {
+            // Tags of the variants, to tell an unknown command from a malformed one.
+            pub const NAMES: &'static [&'static str] = &[$($name),*];
+        }
+    };
+}
+
+message_types! {
+    "nick" => Nick(NickMessage),
+    "private" => Private(PrivateMessage),
+    "chat" => Chat(ChatMessage),
+    "help" => Help(HelpMessage),
+    "quit" => Quit(QuitMessage),
+    "register" => Register(RegisterMessage),
+    "login" => Login(LoginMessage),
+    "logout" => Logout(LogoutMessage),
+    "op" => Op(OpMessage),
+    "kick" => Kick(KickMessage),
+    "ban" => Ban(BanMessage),
+    "mute" => Mute(MuteMessage),
+    "pending" => Pending(PendingMessagesMessage),
+    "clear_pending" => ClearPending(ClearPendingMessage),
+    "read" => Read(ReadMessage),
+    "edit" => Edit(EditMessage),
+    "delete" => Delete(DeleteMessage),
+    "thread" => Thread(ThreadMessage),
+    "react" => React(ReactMessage),
+    "unreact" => Unreact(UnreactMessage),
+    "typing_start" => TypingStart(TypingStartMessage),
+    "typing_stop" => TypingStop(TypingStopMessage),
+    "status" => Status(StatusMessage),
+    "users" => Users(UsersMessage),
+    "ignore" => Ignore(IgnoreMessage),
+    "unignore" => Unignore(UnignoreMessage),
+}
+
+// Server notices, the replies to commands.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct NoticeEvent {
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ChatEvent {
+    pub id: u64,
+    pub sender: String,
+    pub message: String,
+    // Unix time.
+    pub sent_at: u64,
+    pub reply_to: Option<Quote>,
+    // Id of the first message of the thread, for replies.
+    pub thread: Option<u64>,
+    pub reactions: Vec<Reaction>,
+    // Mentioned nicknames, "here" or "all" if everyone was.
+    pub mentions: Vec<String>,
+}
+
+// Sent to the mentioned users besides the chat message, so clients can notify them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MentionEvent {
+    pub id: u64,
+    pub sender: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: usize,
+    pub nicks: Vec<String>,
+}
+
+// All reactions on message `id` after one of them changed.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReactionsEvent {
+    pub id: u64,
+    pub reactions: Vec<Reaction>,
+}
+
+// Relayed typing notifications, `receiver` is None when typing to the chat.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TypingEvent {
+    pub sender: String,
+    pub receiver: Option<String>,
+    pub typing: bool,
+}
+
+// Status of a nickname, the most available one of its connections. Sent to everyone when
+// it changes.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct UserStatus {
+    pub nick: String,
+    pub status: Status,
+    pub message: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct UsersEvent {
+    // Sorted by nickname.
+    pub users: Vec<UserStatus>,
+}
+
+// Start of the message a reply answers.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Quote {
+    pub id: u64,
+    pub sender: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ThreadEvent {
+    pub id: u64,
+    // Oldest first.
+    pub messages: Vec<ChatEvent>,
+}
+
+// Sent to the receiver and echoed to every connection of the sender.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PrivateEvent {
+    pub id: u64,
+    pub sender: String,
+    pub receiver: String,
+    pub message: String,
+    // Unix time, earlier than the delivery for messages that waited for the receiver to
+    // log in.
+    pub sent_at: u64,
+    pub reactions: Vec<Reaction>,
+}
+
+// Sent to everyone who received message `id`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct EditEvent {
+    pub id: u64,
+    pub message: String,
+    pub edited_by: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DeleteEvent {
+    pub id: u64,
+    pub deleted_by: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiptStatus {
+    Delivered,
+    Read,
+}
+
+// Tells the sender of private message `id` what happened to it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ReceiptEvent {
+    pub id: u64,
+    pub receiver: String,
+    pub status: ReceiptStatus,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    // Not a JSON or MessagePack map at all.
+    Malformed,
+    // Text frame on a MessagePack connection or binary frame on a JSON one.
+    UnexpectedFrame,
+    MissingMessageType,
+    UnknownMessageType,
+    // Known message type with missing or mistyped fields.
+    InvalidFields,
+}
+
+// Reply to a message that could not be parsed as a command.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ErrorEvent {
+    pub code: ErrorCode,
+    // The `message_type` of the offending message, the field name is taken by the tag.
+    pub command: Option<String>,
+    pub detail: String,
+}
+
+// Messages sent by the server, tagged the same way as the client's.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 #[serde(tag = "message_type")]
-pub enum MessageType {
-    #[serde(rename = "nick")]
-    Nick(NickMessage),
-    #[serde(rename = "private")]
-    Private(PrivateMessage),
+pub enum ServerMessage {
+    #[serde(rename = "notice")]
+    Notice(NoticeEvent),
     #[serde(rename = "chat")]
-    Chat(ChatMessage),
-    #[serde(rename = "help")]
-    Help(HelpMessage),
-    #[serde(rename = "quit")]
-    Quit(QuitMessage),
+    Chat(ChatEvent),
+    #[serde(rename = "private")]
+    Private(PrivateEvent),
+    #[serde(rename = "receipt")]
+    Receipt(ReceiptEvent),
+    #[serde(rename = "edit")]
+    Edit(EditEvent),
+    #[serde(rename = "delete")]
+    Delete(DeleteEvent),
+    #[serde(rename = "thread")]
+    Thread(ThreadEvent),
+    #[serde(rename = "reactions")]
+    Reactions(ReactionsEvent),
+    #[serde(rename = "mention")]
+    Mention(MentionEvent),
+    #[serde(rename = "typing")]
+    Typing(TypingEvent),
+    #[serde(rename = "status")]
+    Status(UserStatus),
+    #[serde(rename = "users")]
+    Users(UsersEvent),
+    #[serde(rename = "error")]
+    Error(ErrorEvent),
 }
@@ -1,23 +1,72 @@
-use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::net::TcpListener;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
 
+use crate::ws::connection_limit::{ConnectionLimits, ConnectionTracker, LimitExceeded};
+use crate::ws::http_header::HttpHeader;
+use crate::ws::http_response::{HttpResponse, StatusType};
 use crate::ws::http_router::HttpRouter;
-use crate::ws::http_session::{HttpHandleError, HttpSession};
-use crate::ws::ws_session::WsSession;
+use crate::ws::http_session::HttpSession;
+use crate::ws::moderation::{BanList, BanTarget};
+
+// Connections over the limits that are answered at once, the ones beyond are closed
+// without a response.
+const MAX_PENDING_REJECTS: usize = 64;
+// Time a rejected client gets to read the response.
+const REJECT_TIMEOUT: Duration = Duration::from_secs(1);
 
 pub struct WsServer {
     router: Arc<HttpRouter>,
+    bans: Option<Arc<BanList>>,
+    connections: Arc<ConnectionTracker>,
+    rejects: Arc<Semaphore>,
+    websocket_config: WebSocketConfig,
+}
+
+// Chat messages are small, tungstenite's defaults allow megabytes per message and an
+// unbounded write buffer for clients that don't read.
+pub fn default_websocket_config() -> WebSocketConfig {
+    WebSocketConfig {
+        max_message_size: Some(64 * 1024),
+        max_frame_size: Some(64 * 1024),
+        write_buffer_size: 128 * 1024,
+        max_write_buffer_size: 1024 * 1024,
+        ..WebSocketConfig::default()
+    }
 }
 
 impl WsServer {
     pub fn new(router: HttpRouter) -> Self {
         Self {
             router: Arc::new(router),
+            bans: None,
+            connections: Arc::new(ConnectionTracker::new(ConnectionLimits::default())),
+            rejects: Arc::new(Semaphore::new(MAX_PENDING_REJECTS)),
+            websocket_config: default_websocket_config(),
         }
     }
 
+    // Incoming messages or frames over the configured sizes close the session with 1009.
+    pub fn set_websocket_config(&mut self, config: WebSocketConfig) -> &mut Self {
+        self.websocket_config = config;
+        self
+    }
+
+    pub fn set_connection_limits(&mut self, limits: ConnectionLimits) -> &mut Self {
+        self.connections = Arc::new(ConnectionTracker::new(limits));
+        self
+    }
+
+    // Connections from banned addresses are closed as soon as they are accepted.
+    pub fn set_ban_list(&mut self, bans: Arc<BanList>) -> &mut Self {
+        self.bans = Some(bans);
+        self
+    }
+
     pub async fn start(self, add: &str) {
         let tcp_listener = TcpListener::bind(add)
             .await
@@ -26,35 +75,79 @@ impl WsServer {
             })
             .unwrap();
 
-        let clients = Arc::new(Mutex::new(HashMap::new()));
-
         loop {
-            let mut socket = if let Ok((socket, remote_addr)) = tcp_listener.accept().await {
+            let (mut socket, remote_addr) = match tcp_listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(_) => {
+                    eprintln!("Could not accept new Tcp connection");
+                    continue;
+                }
+            };
+
+            if self
+                .bans
+                .as_ref()
+                .is_some_and(|bans| bans.is_banned(&BanTarget::Ip(remote_addr.ip())))
+            {
                 println!(
-                    "New connection {}:{}",
-                    remote_addr.ip().to_string(),
-                    remote_addr.port()
+                    "Rejected connection from banned address {}",
+                    remote_addr.ip()
                 );
-                socket
-            } else {
-                eprintln!("Could not accept new Tcp connection");
                 continue;
+            }
+
+            let mut connection = match self.connections.acquire(remote_addr.ip()) {
+                Ok(connection) => connection,
+                Err(e) => {
+                    println!("Rejected connection from {}: {}", remote_addr.ip(), e);
+                    if let Ok(permit) = Arc::clone(&self.rejects).try_acquire_owned() {
+                        tokio::spawn(async move {
+                            let _ = tokio::time::timeout(REJECT_TIMEOUT, reject(socket, e)).await;
+                            drop(permit);
+                        });
+                    }
+                    continue;
+                }
             };
+            println!("New connection {}:{}", remote_addr.ip(), remote_addr.port());
 
             let router_copy = Arc::clone(&self.router);
-            let clients_copy = Arc::clone(&clients);
+            let websocket_config = self.websocket_config;
             tokio::spawn(async move {
                 let mut http_session = HttpSession::new(router_copy);
-                if let Err(HttpHandleError::WebsocketProtocol) =
-                    http_session.handle_socket(&mut socket).await
-                {
-                    if let Some(mut ws_session) = WsSession::new(socket, clients_copy).await {
-                        ws_session.handle_ws_connection().await;
-                    } else {
-                        eprintln!("Could not accept websocket connection");
+                if let Ok(Some(upgrade)) = http_session.handle_socket(&mut socket).await {
+                    match connection.upgrade() {
+                        Ok(()) => upgrade.run(socket, websocket_config).await,
+                        Err(e) => {
+                            println!("Rejected websocket from {}: {}", remote_addr.ip(), e);
+                            upgrade
+                                .reject(socket, websocket_config, CloseCode::Again, e.to_string())
+                                .await;
+                        }
                     }
                 }
             });
         }
     }
 }
+
+// Answers a connection over the limits with 503 without reading the request.
+async fn reject(mut socket: TcpStream, reason: LimitExceeded) {
+    let response = HttpResponse::new(
+        StatusType::ServiceUnavailable,
+        vec![
+            HttpHeader::new("Content-Type", "text/plain; charset=utf-8"),
+            HttpHeader::new("Connection", "close"),
+            HttpHeader::new("Retry-After", "10"),
+        ],
+        reason.to_string().into_bytes(),
+    );
+    if socket.write_all(&response.bytes()).await.is_err() || socket.shutdown().await.is_err() {
+        return;
+    }
+
+    // Closing with unread request bytes would reset the connection before the client
+    // reads the response.
+    let mut buffer = [0; 1024];
+    while let Ok(1..) = socket.read(&mut buffer).await {}
+}
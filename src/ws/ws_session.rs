@@ -1,86 +1,385 @@
-use crate::ws::ws_message::MessageType;
+use crate::ws::codec::Codec;
+use crate::ws::identity::Identity;
+use crate::ws::ignore_list::{IgnoreList, MAX_IGNORED};
+use crate::ws::mentions::{is_mention_everyone, parse_mentions};
+use crate::ws::message_log::{MessageLog, SentMessage};
+use crate::ws::moderation::{now, parse_duration, BanTarget, Moderation};
+use crate::ws::offline_queue::OfflineQueue;
+use crate::ws::rate_limit::{
+    FloodGuard, IpRateLimiter, MessageKind, RateLimits, TokenBucket, Verdict,
+};
+use crate::ws::receipts::{MessageIds, ReadReceipts};
+use crate::ws::user_store::{UserStore, UserStoreError};
+use crate::ws::ws_message::{
+    BanMessage, ChatEvent, ChatMessage, DeleteEvent, DeleteMessage, EditEvent, EditMessage,
+    ErrorCode, ErrorEvent, IgnoreMessage, KickMessage, LoginMessage, MentionEvent, MessageType,
+    MuteMessage, NickMessage, NoticeEvent, OpMessage, PrivateEvent, PrivateMessage, Quote,
+    Reaction, ReactionsEvent, ReadMessage, ReceiptEvent, ReceiptStatus, RegisterMessage,
+    ServerMessage, Status, StatusMessage, ThreadEvent, ThreadMessage, TypingEvent, UnignoreMessage,
+    UserStatus, UsersEvent,
+};
 use futures::stream::{SplitSink, SplitStream};
 use futures_util::{SinkExt, StreamExt};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::{
     io::{AsyncRead, AsyncWrite},
-    sync::{mpsc::channel, Mutex},
+    sync::{
+        mpsc::{
+            channel, error::TrySendError, unbounded_channel, Sender, UnboundedReceiver,
+            UnboundedSender,
+        },
+        Mutex, Notify,
+    },
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        protocol::{frame::coding::CloseCode, CloseFrame, Message},
+        Error,
+    },
+    WebSocketStream,
 };
-use tokio_tungstenite::{accept_async, tungstenite::protocol::Message, WebSocketStream};
 
 const USAGE_MSG: &str = "To use chat, you need to set your nickname.
 Usage:
     /nick <nickname>                - set your nickname
     /quit                           - leave chat
     /help                           - show help
-    /private <nickname> <message>   - send private message";
+    /private <nickname> <message>   - send private message
+    /edit <id> <message>            - change one of your recent messages
+    /delete <id>                    - delete one of your recent messages
+    /reply <id> <message>           - answer a chat message
+    /thread <id>                    - show a message and all replies to it
+    @<nickname> in a message        - notify that user
+    /react <id> <emoji>             - react to a message, e.g. /react 42 :+1:
+    /unreact <id> <emoji>           - take back your reaction
+    /register <nickname> <password> - register your nickname
+    /login <nickname> <password>    - log in to registered nickname
+    /logout                         - log out and leave chat
+    /pending                        - list messages sent while you were offline
+    /pending clear                  - discard messages sent while you were offline
+    /away [message]                 - mark yourself away, private messages get a reply
+    /dnd [message]                  - do not disturb, mentions are not notified
+    /back                           - mark yourself online again
+    /status <message>               - set a status text while online
+    /users                          - list connected users and their status
+    /ignore [nickname]              - stop seeing messages of a user, or list ignored users
+    /unignore <nickname>            - see messages of a user again
+Operators:
+    /op <nickname>                  - make user an operator
+    /kick <nickname> [reason]       - disconnect user
+    /ban <nickname|ip> [duration]   - ban user or address, e.g. 30m, 2h, 7d
+    /mute <nickname> [duration]     - stop user from sending messages
+    @here or @all in a message      - notify everyone";
+
+pub const MAX_MESSAGE_LEN: usize = 2000;
+pub const MAX_NICKNAME_LEN: usize = 32;
+// Characters of the answered message repeated in a reply.
+const MAX_QUOTE_LEN: usize = 100;
+// Latest messages of a thread sent at once, long threads would not fit the write buffer.
+const MAX_THREAD_MESSAGES: usize = 100;
+// Characters of the text set with a status.
+const MAX_STATUS_LEN: usize = 100;
+// Long enough for emoji sequences and shortcodes.
+const MAX_EMOJI_LEN: usize = 32;
+// Typing notifications end by themselves when the client stops refreshing them.
+const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+// Consecutive messages that fail to parse before the session is closed.
+const MAX_INVALID_MESSAGES: u32 = 10;
+// Close frame reasons have to fit in a control frame.
+const MAX_CLOSE_REASON_LEN: usize = 123;
+// How long a closing session waits for the client to answer its close frame.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+// Messages waiting to be written to one client, a client that stops reading is
+// disconnected once this many pile up.
+const OUTBOX_CAPACITY: usize = 256;
 
 type SocketReadHalf<S> = SplitStream<WebSocketStream<S>>;
-type SocketWriteHalf<S> = Arc<Mutex<SplitSink<WebSocketStream<S>, Message>>>;
-pub type Clients<S> = Arc<Mutex<HashMap<String, SocketWriteHalf<S>>>>;
+// Messages queued here are encoded and written to the socket in order by the session's
+// writer task.
+#[derive(Clone)]
+struct Outbox {
+    queue: Sender<Outgoing>,
+    // Notified when the queue is full, the writer then drops the connection.
+    overflow: Arc<Notify>,
+}
+
+impl Outbox {
+    fn send(&self, outgoing: Outgoing) {
+        if let Err(TrySendError::Full(_)) = self.queue.try_send(outgoing) {
+            self.overflow.notify_one();
+        }
+    }
+
+    fn same_channel(&self, other: &Outbox) -> bool {
+        self.queue.same_channel(&other.queue)
+    }
+}
+// The sessions of one account share its nickname, a guest nickname has one connection.
+pub type Clients = Arc<Mutex<HashMap<String, Vec<Client>>>>;
+
+enum Outgoing {
+    Message(ServerMessage),
+    Close(CloseFrame<'static>),
+    // Switches the encoding of the messages that follow.
+    Codec(Codec),
+}
+
+// Audience the user is typing to, None for the chat.
+struct Typing {
+    receiver: Option<String>,
+    until: Instant,
+}
+
+// Status of one connection.
+#[derive(Clone)]
+struct Presence {
+    status: Status,
+    message: Option<String>,
+    // Set when the user was marked away for being inactive, undone by their next message.
+    automatic: bool,
+}
+
+impl Presence {
+    fn online() -> Self {
+        Self {
+            status: Status::Online,
+            message: None,
+            automatic: false,
+        }
+    }
+}
+
+// Requests other sessions make to a session.
+enum Control {
+    Kick(String),
+    Op,
+}
+
+// Session of a user that joined the chat, as seen by the other sessions.
+#[derive(Clone)]
+pub struct Client {
+    outbox: Outbox,
+    control: UnboundedSender<Control>,
+    ip: Option<IpAddr>,
+    identity: Identity,
+    presence: Arc<std::sync::Mutex<Presence>>,
+    ignored: Arc<std::sync::Mutex<HashSet<String>>>,
+}
+
+// State shared by all sessions of one chat endpoint.
+#[derive(Clone)]
+pub struct ChatState {
+    pub clients: Clients,
+    pub users: Arc<dyn UserStore + Send + Sync>,
+    pub moderation: Arc<Moderation>,
+    pub offline_queue: Option<Arc<OfflineQueue>>,
+    pub ignore_list: Option<Arc<IgnoreList>>,
+    pub message_ids: Arc<MessageIds>,
+    pub receipts: Arc<ReadReceipts>,
+    pub message_log: Arc<MessageLog>,
+    pub edit_window: Duration,
+    pub away_after: Duration,
+    pub rate_limits: RateLimits,
+    pub global_limit: Arc<std::sync::Mutex<TokenBucket>>,
+    // Password attempts with /login per client address.
+    pub login_limiter: Arc<IpRateLimiter>,
+}
 
 pub struct WsSession<S> {
     socket_read_half: SocketReadHalf<S>,
-    socket_write_half: SocketWriteHalf<S>,
-    clients: Clients<S>,
+    outbox: Outbox,
+    // None until the first frame if the client did not negotiate a subprotocol.
+    codec: Option<Codec>,
+    clients: Clients,
+    users: Arc<dyn UserStore + Send + Sync>,
+    moderation: Arc<Moderation>,
+    offline_queue: Option<Arc<OfflineQueue>>,
+    ignore_list: Option<Arc<IgnoreList>>,
+    // Nicknames whose messages do not reach this connection, shared with its entry in
+    // `clients`.
+    ignored: Arc<std::sync::Mutex<HashSet<String>>>,
+    message_ids: Arc<MessageIds>,
+    receipts: Arc<ReadReceipts>,
+    message_log: Arc<MessageLog>,
+    edit_window: Duration,
+    away_after: Duration,
+    // Shared with the entry of this connection in `clients`.
+    presence: Arc<std::sync::Mutex<Presence>>,
+    last_active: Instant,
+    flood_guard: FloodGuard,
+    global_limit: Arc<std::sync::Mutex<TokenBucket>>,
+    login_limiter: Arc<IpRateLimiter>,
+    // Set when the session went over its rate limits.
+    muted_until: Option<Instant>,
+    // Set while the others were told the user is typing.
+    typing: Option<Typing>,
+    // Reset by every valid command.
+    invalid_messages: u32,
+    ip: Option<IpAddr>,
+    control_tx: UnboundedSender<Control>,
+    control_rx: UnboundedReceiver<Control>,
     nickname: Arc<Mutex<Option<String>>>,
+    // Identity of this connection while it is not logged in.
+    guest: Identity,
+    authenticated: bool,
+    operator: bool,
 }
 
 impl<S> WsSession<S>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    pub async fn new(socket: S, clients: Clients<S>) -> Option<Self> {
-        let ws_socket = accept_async(socket)
-            .await
-            .map_err(|e| {
-                eprintln!("Could not accept websocket connection, error {}", e);
-                return ();
-            })
-            .unwrap();
-
-        let (write_half, read_half) = ws_socket.split();
-        let write_half = Arc::new(Mutex::new(write_half));
-        write_half
-            .lock()
-            .await
-            .send(Message::Text(USAGE_MSG.to_string()))
-            .await
-            .expect("Should send usage message");
+    // `user` is the nickname authenticated during the handshake, if any. Without a
+    // `codec` the server writes JSON until the first frame of the client tells otherwise.
+    pub async fn new(
+        ws_socket: WebSocketStream<S>,
+        state: ChatState,
+        user: Option<String>,
+        ip: Option<IpAddr>,
+        codec: Option<Codec>,
+    ) -> Option<Self> {
+        let (mut write_half, read_half) = ws_socket.split();
+        let write_codec = codec.unwrap_or(Codec::Json);
+        let usage = ServerMessage::Notice(NoticeEvent {
+            message: USAGE_MSG.to_string(),
+        });
+        let sent = match write_codec.encode(&usage) {
+            Ok(message) => write_half.send(message).await.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = sent {
+            eprintln!("Could not send usage message, error: {}", e);
+            return None;
+        }
 
-        Some(Self {
+        let (control_tx, control_rx) = unbounded_channel();
+        let mut session = Self {
             socket_read_half: read_half,
-            socket_write_half: write_half,
-            clients,
+            outbox: spawn_writer(write_half, write_codec),
+            codec,
+            clients: state.clients,
+            users: state.users,
+            moderation: state.moderation,
+            offline_queue: state.offline_queue,
+            ignore_list: state.ignore_list,
+            ignored: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            message_ids: state.message_ids,
+            receipts: state.receipts,
+            message_log: state.message_log,
+            edit_window: state.edit_window,
+            away_after: state.away_after,
+            presence: Arc::new(std::sync::Mutex::new(Presence::online())),
+            last_active: Instant::now(),
+            flood_guard: FloodGuard::new(&state.rate_limits),
+            global_limit: state.global_limit,
+            login_limiter: state.login_limiter,
+            muted_until: None,
+            typing: None,
+            invalid_messages: 0,
+            ip,
+            control_tx,
+            control_rx,
             nickname: Arc::new(Mutex::new(None)),
-        })
+            guest: Identity::guest(),
+            authenticated: false,
+            operator: false,
+        };
+
+        if let Some(nick) = user {
+            session.login_with_token(nick).await;
+        }
+
+        Some(session)
     }
 
     pub async fn handle_ws_connection(&mut self) {
-        let (tx, mut rx) = channel::<String>(5);
+        let (tx, mut rx) = channel::<ChatEvent>(5);
         loop {
             let clients_clone = Arc::clone(&self.clients);
-            let client_nickname = Arc::clone(&self.nickname);
+            let typing_until = self.typing.as_ref().map(|typing| typing.until);
+            let away_at = (self.away_after > Duration::ZERO
+                && self.presence.lock().unwrap().status == Status::Online)
+                .then(|| self.last_active + self.away_after);
 
             tokio::select! {
                 message = rx.recv() => {
-                    if let Some(message) = message {
-                        if let Some(ref nick) = *client_nickname.lock().await {
-                            Self::brodcast_message(
-                                nick,
-                                message.clone(),
-                                Arc::clone(&clients_clone),
-                            )
-                            .await;
+                    if let Some(event) = message {
+                        self.message_log.record(
+                            event.id,
+                            SentMessage {
+                                sender: event.sender.clone(),
+                                author: self.identity(&event.sender),
+                                receiver: None,
+                                message: event.message.clone(),
+                                sent_at: event.sent_at,
+                                reply_to: event.reply_to.as_ref().map(|quote| quote.id),
+                                thread: event.thread,
+                                reactions: BTreeMap::new(),
+                                mentions: event.mentions.clone(),
+                            },
+                        );
+                        let mention = MentionEvent {
+                            id: event.id,
+                            sender: event.sender.clone(),
+                            message: event.message.clone(),
+                        };
+                        let mentions = event.mentions.clone();
+                        // Sent back to the sender too, with the id to edit it.
+                        let message = ServerMessage::Chat(event);
+                        let clients = Arc::clone(&clients_clone);
+                        Self::brodcast_message(&mention.sender, message, clients, true).await;
+                        send_mentions(&clients_clone, &mentions, mention).await;
+                    }
+                }
+                _ = tokio::time::sleep_until(
+                    typing_until.unwrap_or_else(Instant::now).into()
+                ), if typing_until.is_some() => {
+                    let nick = self.nickname.lock().await.clone();
+                    if let Some(nick) = nick {
+                        self.stop_typing(&nick).await;
+                    }
+                }
+                _ = tokio::time::sleep_until(
+                    away_at.unwrap_or_else(Instant::now).into()
+                ), if away_at.is_some() => {
+                    self.set_presence(Presence {
+                        status: Status::Away,
+                        message: None,
+                        automatic: true,
+                    })
+                    .await;
+                }
+                // The writer stopped, the client is gone or did not keep up.
+                _ = self.outbox.queue.closed() => {
+                    self.handle_close_message().await;
+                    return;
+                }
+                control = self.control_rx.recv() => {
+                    match control {
+                        Some(Control::Kick(reason)) => {
+                            self.close(CloseCode::Policy, reason).await;
+                            return;
+                        }
+                        Some(Control::Op) if self.authenticated => {
+                            self.operator = true;
+                            self.send_to_self(String::from("You are now an operator"));
                         }
+                        Some(Control::Op) => {}
+                        // The session keeps a sender, the channel can't be closed.
+                        None => {}
                     }
                 }
                 message = self.socket_read_half.next() => {
                     let msg = match message {
-                        Some(Ok(Message::Text(msg))) => msg,
-                        Some(Ok(Message::Binary(data))) => String::from_utf8(data).unwrap(),
-                        Some(Ok(Message::Close(_))) => {
+                        Some(Ok(msg @ (Message::Text(_) | Message::Binary(_)))) => msg,
+                        Some(Err(Error::Capacity(e))) => {
+                            self.close(CloseCode::Size, e.to_string()).await;
+                            return;
+                        }
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
                             self.handle_close_message().await;
                             return;
                         }
@@ -90,124 +389,2383 @@ where
                         }
                     };
 
-                    let msg= if let Some(message) = self.parse_command(msg) {message} else {
-                        self.send_to_self(format!("Command not supported."));
-                        continue;
+                    let msg = self.parse_command(&msg);
+                    if let Err(ErrorEvent { code: ErrorCode::UnexpectedFrame, .. }) = msg {
+                        self.close(
+                            CloseCode::Unsupported,
+                            String::from("Frame type does not match the negotiated protocol"),
+                        )
+                        .await;
+                        return;
+                    }
+                    let kind = match msg {
+                        Ok(MessageType::Chat(_)) => MessageKind::Chat,
+                        Ok(MessageType::Private(_)) => MessageKind::Private,
+                        Ok(MessageType::TypingStart(_)) => MessageKind::Typing,
+                        _ => MessageKind::Command,
+                    };
+                    match self.flood_guard.check(kind) {
+                        Verdict::Allow => {}
+                        Verdict::Ignore => continue,
+                        Verdict::Warn => {
+                            self.send_to_self(String::from(
+                                "You are sending messages too fast, slow down",
+                            ));
+                            continue;
+                        }
+                        Verdict::Mute(duration) => {
+                            self.muted_until = Some(Instant::now() + duration);
+                            self.send_to_self(format!(
+                                "You are muted for {}s for flooding",
+                                duration.as_secs()
+                            ));
+                            continue;
+                        }
+                        Verdict::Disconnect => {
+                            self.close(CloseCode::Policy, String::from("Flooding")).await;
+                            return;
+                        }
+                    }
+
+                    let msg = match msg {
+                        Ok(message) => {
+                            self.invalid_messages = 0;
+                            message
+                        }
+                        Err(error) => {
+                            self.invalid_messages += 1;
+                            if self.invalid_messages >= MAX_INVALID_MESSAGES {
+                                self.close(
+                                    CloseCode::Policy,
+                                    String::from("Too many invalid messages"),
+                                )
+                                .await;
+                                return;
+                            }
+                            send_event(&self.outbox, ServerMessage::Error(error));
+                            continue;
+                        }
                     };
 
+                    self.last_active = Instant::now();
+                    let automatic = self.presence.lock().unwrap().automatic;
+                    if automatic && !matches!(msg, MessageType::Status(_)) {
+                        self.set_presence(Presence::online()).await;
+                    }
+
                     match msg {
-                        MessageType::Nick(nick_message) => {
-                            if self.nickname.lock().await.is_none() {
-                                *self.nickname.lock().await = Some(nick_message.nick.clone());
-                                self.clients.lock().await.insert(
-                                    nick_message.nick.clone(),
-                                    Arc::clone(&self.socket_write_half),
-                                );
-                                self.send_to_self(format!(
-                                    "Hello {}, now you can send messages",
-                                    nick_message.nick
+                        MessageType::Nick(nick_message) => self.handle_nick(nick_message).await,
+                        MessageType::Chat(chat_message) => {
+                            let Some(sender) = self.nickname.lock().await.clone() else {
+                                self.send_to_self(String::from(
+                                    "Please enter your nickname: /nick <your_nickname>",
                                 ));
                                 continue;
+                            };
+                            let Some(parent) = self.find_parent(chat_message.reply_to) else {
+                                continue;
+                            };
+
+                            if self.is_muted().await {
+                                self.send_to_self(String::from("You are muted"));
+                            } else if let Some(error) = check_message_len(&chat_message.message) {
+                                self.send_to_self(error);
+                            } else if !self.global_limit.lock().unwrap().try_take() {
+                                self.send_to_self(String::from(
+                                    "Server is busy, message was not sent",
+                                ));
+                            } else {
+                                self.stop_typing(&sender).await;
+                                let mentions = self.find_mentions(&chat_message.message).await;
+                                let event = self.chat_event(sender, chat_message, parent, mentions);
+                                if let Err(e) = tx.send(event).await {
+                                    eprintln!("Could not brodcast message, error: {}", e);
+                                }
                             }
                         }
-                        MessageType::Chat(chat_message) => match *self.nickname.lock().await {
-                            None => {
+                        MessageType::Private(private_message) => {
+                            let Some(sender) = self.nickname.lock().await.clone() else {
                                 self.send_to_self(String::from(
                                     "Please enter your nickname: /nick <your_nickname>",
                                 ));
+                                continue;
+                            };
+                            if self.is_muted().await {
+                                self.send_to_self(String::from("You are muted"));
+                                continue;
                             }
-                            Some(_) => match tx.send(chat_message.message).await {
-                                Ok(_) => {}
-                                Err(e) => {
-                                    eprintln!("Could not brodcast message, error: {}", e);
-                                }
-                            },
-                        },
-                        MessageType::Private(private_message) => {
-                            let clients = self.clients.lock().await;
-                            if let Some(c) = clients.get(&private_message.receiver) {
-                                c.lock()
-                                    .await
-                                    .send(Message::Text(private_message.message))
-                                    .await
-                                    .expect("Private message to be sent.");
-                            } else {
-                                self.send_to_self(format!(
-                                    "Client with nickname: {} is not connected to chat",
-                                    private_message.receiver
+                            if let Some(error) = check_message_len(&private_message.message) {
+                                self.send_to_self(error);
+                                continue;
+                            }
+                            if !self.global_limit.lock().unwrap().try_take() {
+                                self.send_to_self(String::from(
+                                    "Server is busy, message was not sent",
                                 ));
+                                continue;
                             }
+
+                            self.stop_typing(&sender).await;
+                            self.send_private(sender, private_message).await;
                         }
                         MessageType::Quit(_) => {
-                            let mut nick = self.nickname.lock().await;
+                            let nick = self.nickname.lock().await.take();
 
-                            match *nick {
+                            match nick {
                                 Some(ref n) => {
-                                    self.send_to_self(format!("You left the chat."));
-                                    self.clients.lock().await.remove(n);
-                                    *nick = None;
+                                    self.send_to_self("You left the chat.".to_string());
+                                    self.remove_client(n).await;
+                                    self.ignored.lock().unwrap().clear();
+                                    self.authenticated = false;
+                                    self.operator = false;
                                 }
                                 None => {
-                                    self.send_to_self(format!(
-                                        "Leave impossible, you are not in the chat"
-                                    ));
+                                    self.send_to_self("Leave impossible, you are not in the chat".to_string());
                                 }
                             }
                         }
                         MessageType::Help(_) => {
                             self.send_to_self(USAGE_MSG.to_string());
                         }
+                        MessageType::Register(register_message) => {
+                            self.handle_register(register_message).await
+                        }
+                        MessageType::Login(login_message) => self.handle_login(login_message).await,
+                        MessageType::Logout(_) => self.handle_logout().await,
+                        MessageType::Op(op_message) => self.handle_op(op_message).await,
+                        MessageType::Kick(kick_message) => self.handle_kick(kick_message).await,
+                        MessageType::Ban(ban_message) => self.handle_ban(ban_message).await,
+                        MessageType::Mute(mute_message) => self.handle_mute(mute_message).await,
+                        MessageType::Pending(_) => self.handle_pending().await,
+                        MessageType::ClearPending(_) => self.handle_clear_pending().await,
+                        MessageType::Read(read_message) => self.handle_read(read_message).await,
+                        MessageType::Edit(edit_message) => self.handle_edit(edit_message).await,
+                        MessageType::Delete(delete_message) => {
+                            self.handle_delete(delete_message).await
+                        }
+                        MessageType::Thread(thread_message) => {
+                            self.handle_thread(thread_message).await
+                        }
+                        MessageType::React(react_message) => {
+                            self.handle_react(react_message.id, react_message.emoji, true)
+                                .await
+                        }
+                        MessageType::Unreact(unreact_message) => {
+                            self.handle_react(unreact_message.id, unreact_message.emoji, false)
+                                .await
+                        }
+                        MessageType::TypingStart(typing_message) => {
+                            self.handle_typing_start(typing_message.receiver).await
+                        }
+                        MessageType::TypingStop(_) => {
+                            let nick = self.nickname.lock().await.clone();
+                            if let Some(nick) = nick {
+                                self.stop_typing(&nick).await;
+                            }
+                        }
+                        MessageType::Status(status_message) => {
+                            self.handle_status(status_message).await
+                        }
+                        MessageType::Users(_) => self.handle_users().await,
+                        MessageType::Ignore(ignore_message) => {
+                            self.handle_ignore(ignore_message).await
+                        }
+                        MessageType::Unignore(unignore_message) => {
+                            self.handle_unignore(unignore_message).await
+                        }
                     }
                 }
             }
         }
     }
 
-    async fn brodcast_message(sender_nick: &str, message: String, clients: Clients<S>) {
-        for (nick, client) in clients.lock().await.iter() {
-            if nick == sender_nick {
-                continue;
-            }
+    async fn handle_nick(&mut self, nick_message: NickMessage) {
+        if self.nickname.lock().await.is_some() {
+            return;
+        }
 
-            let message_clone = message.clone();
-            let client_clone = client.clone();
-            tokio::spawn(async move {
-                client_clone
-                    .lock()
-                    .await
-                    .send(Message::text(format!("{}", message_clone)))
-                    .await
-                    .expect("Message should be sent");
-            });
+        if let Some(error) = self.check_nickname_available(&nick_message.nick) {
+            self.send_to_self(error);
+            return;
+        }
+
+        if self.users.is_registered(&nick_message.nick) {
+            self.send_to_self(format!(
+                "Nickname {} is registered, please use /login <nickname> <password>",
+                nick_message.nick
+            ));
+            return;
+        }
+
+        if !self
+            .join(nick_message.nick.clone(), self.guest.clone())
+            .await
+        {
+            self.send_to_self(in_use(&nick_message.nick));
+            return;
         }
+        self.send_to_self(format!(
+            "Hello {}, now you can send messages",
+            nick_message.nick
+        ));
     }
 
-    async fn handle_close_message(&self) {
-        match *self.nickname.lock().await {
-            Some(ref nick) => {
-                self.clients.lock().await.remove(nick);
+    async fn handle_register(&mut self, register_message: RegisterMessage) {
+        let current_nick = self.nickname.lock().await.clone();
+        match current_nick {
+            Some(ref nick) if *nick != register_message.nick => {
+                self.send_to_self(format!(
+                    "You are in the chat as {}, use /quit before registering another nickname",
+                    nick
+                ));
+                return;
+            }
+            Some(_) => {}
+            None => {
+                if let Some(error) = self.check_nickname_available(&register_message.nick) {
+                    self.send_to_self(error);
+                    return;
+                }
+                if self.users.is_registered(&register_message.nick) {
+                    self.send_to_self(format!(
+                        "Could not register {}: {}",
+                        register_message.nick,
+                        UserStoreError::AlreadyRegistered
+                    ));
+                    return;
+                }
+                // Held for the whole registration, so nobody takes the nickname while the
+                // password is hashed.
+                if !self
+                    .join(register_message.nick.clone(), self.guest.clone())
+                    .await
+                {
+                    self.send_to_self(in_use(&register_message.nick));
+                    return;
+                }
             }
-            None => {}
         }
-        println!("Client disconnected");
+
+        let users = Arc::clone(&self.users);
+        let nick = register_message.nick.clone();
+        let result =
+            tokio::task::spawn_blocking(move || users.register(&nick, &register_message.password))
+                .await;
+
+        match result {
+            Ok(Ok(())) => {
+                self.set_identity(
+                    &register_message.nick,
+                    Identity::Account(register_message.nick.clone()),
+                )
+                .await;
+                self.set_logged_in(&register_message.nick).await;
+                self.send_to_self(format!(
+                    "Nickname {} registered, you are logged in",
+                    register_message.nick
+                ));
+            }
+            Ok(Err(e)) => {
+                self.send_to_self(format!(
+                    "Could not register {}: {}",
+                    register_message.nick, e
+                ));
+            }
+            Err(e) => {
+                eprintln!("Registration task failed, error: {}", e);
+                self.send_to_self(String::from("Registration failed, please try again"));
+            }
+        }
+
+        // Gives back the nickname reserved above.
+        if current_nick.is_none() && !self.authenticated {
+            self.leave().await;
+        }
     }
 
-    fn send_to_self(&self, message: String) {
-        let socket_write_half_copy = Arc::clone(&self.socket_write_half);
-        tokio::spawn(async move {
-            socket_write_half_copy
-                .lock()
-                .await
-                .send(Message::text(format!("{}", message)))
+    async fn handle_login(&mut self, login_message: LoginMessage) {
+        if self.authenticated {
+            self.send_to_self(String::from("You are already logged in, use /logout first"));
+            return;
+        }
+        if self.ip.is_some_and(|ip| !self.login_limiter.try_take(ip)) {
+            self.send_to_self(String::from(
+                "Too many login attempts, please try again later",
+            ));
+            return;
+        }
+
+        let users = Arc::clone(&self.users);
+        let nick = login_message.nick.clone();
+        let verified =
+            tokio::task::spawn_blocking(move || users.verify(&nick, &login_message.password))
                 .await
-                .expect("Message should be sent to self.");
+                .unwrap_or(false);
+
+        if !verified {
+            self.send_to_self(String::from("Invalid nickname or password"));
+            return;
+        }
+
+        if self.moderation.is_nick_banned(&login_message.nick) {
+            self.send_to_self(format!("Nickname {} is banned", login_message.nick));
+            return;
+        }
+
+        self.leave().await;
+        let account = Identity::Account(login_message.nick.clone());
+        if !self.join(login_message.nick.clone(), account).await {
+            self.send_to_self(format!(
+                "{} is already connected to chat",
+                login_message.nick
+            ));
+            return;
+        }
+        self.set_logged_in(&login_message.nick).await;
+        self.send_to_self(format!("Hello {}, you are logged in", login_message.nick));
+        self.deliver_pending(&login_message.nick).await;
+    }
+
+    async fn login_with_token(&mut self, nick: String) {
+        if !is_valid_nickname(&nick) {
+            eprintln!("Access token subject is not a valid nickname: {}", nick);
+            return;
+        }
+
+        if self.moderation.is_nick_banned(&nick) {
+            self.send_to_self(format!("Nickname {} is banned", nick));
+            return;
+        }
+
+        if !self
+            .join(nick.clone(), Identity::Account(nick.clone()))
+            .await
+        {
+            self.send_to_self(format!("{} is already connected to chat", nick));
+            return;
+        }
+        self.set_logged_in(&nick).await;
+        self.send_to_self(format!("Hello {}, you are logged in", nick));
+        self.deliver_pending(&nick).await;
+    }
+
+    async fn handle_logout(&mut self) {
+        if !self.authenticated {
+            self.send_to_self(String::from("You are not logged in"));
+            return;
+        }
+
+        self.leave().await;
+        self.send_to_self(String::from("You are logged out"));
+    }
+
+    // Delivers to every connection of the receiver, or queues the message if they are
+    // offline, and echoes it to every connection of the sender.
+    async fn send_private(&self, sender: String, private_message: PrivateMessage) {
+        if self.ignores(&private_message.receiver, &sender).await {
+            self.send_to_self(undelivered(&private_message.receiver));
+            return;
+        }
+
+        let event = PrivateEvent {
+            id: self.message_ids.next(),
+            sender,
+            receiver: private_message.receiver,
+            message: private_message.message,
+            sent_at: now(),
+            reactions: Vec::new(),
+        };
+
+        let reader = send_to_nick(
+            &self.clients,
+            &event.receiver,
+            ServerMessage::Private(event.clone()),
+        )
+        .await;
+        if reader.is_none() && !self.queue_private_message(&event).await {
+            return;
+        }
+
+        self.message_log.record(
+            event.id,
+            SentMessage {
+                sender: event.sender.clone(),
+                author: self.identity(&event.sender),
+                receiver: Some(event.receiver.clone()),
+                message: event.message.clone(),
+                sent_at: event.sent_at,
+                reply_to: None,
+                thread: None,
+                reactions: BTreeMap::new(),
+                mentions: Vec::new(),
+            },
+        );
+        if event.sender != event.receiver {
+            send_to_nick(
+                &self.clients,
+                &event.sender,
+                ServerMessage::Private(event.clone()),
+            )
+            .await;
+        }
+        if let Some(reader) = reader {
+            self.receipts.delivered(event.id, &event.sender, reader);
+            send_receipt(&self.clients, &event, ReceiptStatus::Delivered).await;
+            self.auto_reply(&event.receiver).await;
+        }
+    }
+
+    // Tells the sender of a private message when the receiver may not read it soon.
+    async fn auto_reply(&self, receiver: &str) {
+        let status = self
+            .clients
+            .lock()
+            .await
+            .get(receiver)
+            .and_then(|connections| user_status(receiver, connections));
+        let Some(status) = status else {
+            return;
+        };
+
+        let text = status
+            .message
+            .map(|message| format!(": {}", message))
+            .unwrap_or_default();
+        match status.status {
+            Status::Online => {}
+            Status::Away => self.send_to_self(format!("{} is away{}", receiver, text)),
+            Status::Dnd => self.send_to_self(format!(
+                "{} does not want to be disturbed{}",
+                receiver, text
+            )),
+        }
+    }
+
+    // Keeps the message for a registered receiver that is offline.
+    async fn queue_private_message(&self, event: &PrivateEvent) -> bool {
+        let receiver = &event.receiver;
+        let Some(ref offline_queue) = self.offline_queue else {
+            self.send_to_self(undelivered(receiver));
+            return false;
+        };
+        if !self.users.is_registered(receiver) {
+            self.send_to_self(undelivered(receiver));
+            return false;
+        }
+
+        let offline_queue = Arc::clone(offline_queue);
+        let (id, queued_receiver, sender, message) = (
+            event.id,
+            receiver.clone(),
+            event.sender.clone(),
+            event.message.clone(),
+        );
+        let queued =
+            run_blocking(move || offline_queue.push(id, &queued_receiver, &sender, &message)).await;
+        match queued {
+            Ok(()) => {
+                self.send_to_self(format!(
+                    "{} is offline, the message will be delivered when they log in",
+                    receiver
+                ));
+                true
+            }
+            Err(e) => {
+                eprintln!("Could not queue message to {}, error: {}", receiver, e);
+                self.send_to_self(undelivered(receiver));
+                false
+            }
+        }
+    }
+
+    async fn deliver_pending(&self, nick: &str) {
+        let Some(ref offline_queue) = self.offline_queue else {
+            return;
+        };
+        let ignored = self.ignored.lock().unwrap().clone();
+        let offline_queue = Arc::clone(offline_queue);
+        let receiver = nick.to_string();
+        let pending: Vec<_> = run_blocking(move || offline_queue.take(&receiver))
+            .await
+            .into_iter()
+            .filter(|message| !ignored.contains(&message.sender))
+            .collect();
+        if pending.is_empty() {
+            return;
+        }
+
+        self.send_to_self(format!(
+            "You have {} messages sent while you were offline",
+            pending.len()
+        ));
+        for message in pending {
+            let event = PrivateEvent {
+                id: message.id,
+                sender: message.sender,
+                receiver: message.receiver,
+                message: message.message,
+                sent_at: message.sent_at,
+                // Only the sender could react before the message was delivered.
+                reactions: self
+                    .message_log
+                    .get(message.id)
+                    .map(|sent| reactions(&sent))
+                    .unwrap_or_default(),
+            };
+            send_event(&self.outbox, ServerMessage::Private(event.clone()));
+            self.receipts
+                .delivered(event.id, &event.sender, self.identity(&event.receiver));
+            send_receipt(&self.clients, &event, ReceiptStatus::Delivered).await;
+        }
+    }
+
+    async fn handle_edit(&self, edit_message: EditMessage) {
+        let Some((editor, sent)) = self.changeable_message(edit_message.id).await else {
+            return;
+        };
+        if self.is_muted().await {
+            self.send_to_self(String::from("You are muted"));
+            return;
+        }
+        if let Some(error) = check_message_len(&edit_message.message) {
+            self.send_to_self(error);
+            return;
+        }
+
+        // Private messages can still wait for their receiver.
+        if let (Some(offline_queue), Some(_)) = (&self.offline_queue, &sent.receiver) {
+            let offline_queue = Arc::clone(offline_queue);
+            let (id, message) = (edit_message.id, edit_message.message.clone());
+            if let Err(e) = run_blocking(move || offline_queue.edit(id, &message)).await {
+                self.send_to_self(format!("Could not edit message: {}", e));
+                return;
+            }
+        }
+        self.message_log
+            .edit(edit_message.id, &edit_message.message);
+        let event = EditEvent {
+            id: edit_message.id,
+            message: edit_message.message,
+            edited_by: editor.clone(),
+        };
+        self.send_to_recipients(&sent, &editor, ServerMessage::Edit(event))
+            .await;
+    }
+
+    async fn handle_delete(&self, delete_message: DeleteMessage) {
+        let Some((deleter, sent)) = self.changeable_message(delete_message.id).await else {
+            return;
+        };
+
+        if let (Some(offline_queue), Some(_)) = (&self.offline_queue, &sent.receiver) {
+            let offline_queue = Arc::clone(offline_queue);
+            let id = delete_message.id;
+            if let Err(e) = run_blocking(move || offline_queue.remove(id)).await {
+                self.send_to_self(format!("Could not delete message: {}", e));
+                return;
+            }
+        }
+        self.message_log.remove(delete_message.id);
+        let event = DeleteEvent {
+            id: delete_message.id,
+            deleted_by: deleter.clone(),
+        };
+        self.send_to_recipients(&sent, &deleter, ServerMessage::Delete(event))
+            .await;
+    }
+
+    // Authors can change their messages within the edit window, operators anyone's.
+    async fn changeable_message(&self, id: u64) -> Option<(String, SentMessage)> {
+        let Some(nick) = self.nickname.lock().await.clone() else {
+            self.send_to_self(String::from(
+                "Please enter your nickname: /nick <your_nickname>",
+            ));
+            return None;
+        };
+        let Some(sent) = self.message_log.get(id) else {
+            self.send_to_self(format!("Message {} does not exist", id));
+            return None;
+        };
+        if sent.author != self.identity(&nick) && !self.operator {
+            self.send_to_self(String::from("You can only change your own messages"));
+            return None;
+        }
+        if now().saturating_sub(sent.sent_at) > self.edit_window.as_secs() {
+            self.send_to_self(format!(
+                "Message {} is older than {}s and can't be changed",
+                id,
+                self.edit_window.as_secs()
+            ));
+            return None;
+        }
+
+        Some((nick, sent))
+    }
+
+    // Outer None if `reply_to` is not a recent chat message, inner None without one.
+    fn find_parent(&self, reply_to: Option<u64>) -> Option<Option<(u64, SentMessage)>> {
+        let Some(id) = reply_to else {
+            return Some(None);
+        };
+
+        match self.message_log.get(id) {
+            Some(sent) if sent.receiver.is_none() => Some(Some((id, sent))),
+            _ => {
+                self.send_to_self(format!(
+                    "Message {} does not exist or is too old to reply to",
+                    id
+                ));
+                None
+            }
+        }
+    }
+
+    // Connected nicknames mentioned in `message`, @here and @all only count for operators.
+    async fn find_mentions(&self, message: &str) -> Vec<String> {
+        let mut mentions = {
+            let clients = self.clients.lock().await;
+            parse_mentions(message, |name| {
+                is_mention_everyone(name) || clients.contains_key(name)
+            })
+        };
+
+        if !self.operator && mentions.iter().any(|mention| is_mention_everyone(mention)) {
+            mentions.retain(|mention| !is_mention_everyone(mention));
+            self.send_to_self(String::from(
+                "Only operators can mention @here and @all, nobody else was notified",
+            ));
+        }
+        mentions
+    }
+
+    fn chat_event(
+        &self,
+        sender: String,
+        chat_message: ChatMessage,
+        parent: Option<(u64, SentMessage)>,
+        mentions: Vec<String>,
+    ) -> ChatEvent {
+        ChatEvent {
+            id: self.message_ids.next(),
+            sender,
+            message: chat_message.message,
+            sent_at: now(),
+            reply_to: parent.as_ref().map(|(id, sent)| quote(*id, sent)),
+            thread: parent.map(|(id, sent)| sent.thread.unwrap_or(id)),
+            reactions: Vec::new(),
+            mentions,
+        }
+    }
+
+    async fn handle_thread(&self, thread_message: ThreadMessage) {
+        if self.nickname.lock().await.is_none() {
+            self.send_to_self(String::from(
+                "Please enter your nickname: /nick <your_nickname>",
+            ));
+            return;
+        }
+
+        let ignored = self.ignored.lock().unwrap().clone();
+        let mut thread = self.message_log.thread(thread_message.id);
+        thread.retain(|(_, sent)| !ignored.contains(&sent.sender));
+        let messages: Vec<ChatEvent> = thread
+            .into_iter()
+            .rev()
+            .take(MAX_THREAD_MESSAGES)
+            .rev()
+            .map(|(id, sent)| ChatEvent {
+                id,
+                reactions: reactions(&sent),
+                reply_to: sent
+                    .reply_to
+                    .and_then(|parent| Some(quote(parent, &self.message_log.get(parent)?))),
+                sender: sent.sender,
+                message: sent.message,
+                sent_at: sent.sent_at,
+                thread: sent.thread,
+                mentions: sent.mentions,
+            })
+            .collect();
+        if messages.is_empty() {
+            self.send_to_self(format!(
+                "Thread {} does not exist or is too old",
+                thread_message.id
+            ));
+            return;
+        }
+
+        send_event(
+            &self.outbox,
+            ServerMessage::Thread(ThreadEvent {
+                id: thread_message.id,
+                messages,
+            }),
+        );
+    }
+
+    async fn handle_react(&self, id: u64, emoji: String, reacted: bool) {
+        let Some(nick) = self.nickname.lock().await.clone() else {
+            self.send_to_self(String::from(
+                "Please enter your nickname: /nick <your_nickname>",
+            ));
+            return;
+        };
+        if !is_valid_emoji(&emoji) {
+            self.send_to_self(format!(
+                "Invalid reaction, it must have 1 to {} characters without spaces",
+                MAX_EMOJI_LEN
+            ));
+            return;
+        }
+        // Private messages can only be seen by their sender and receiver.
+        let visible = self.message_log.get(id).is_some_and(|sent| {
+            sent.receiver.is_none() || sent.sender == nick || sent.receiver == Some(nick.clone())
         });
+        if !visible {
+            self.send_to_self(format!("Message {} does not exist", id));
+            return;
+        }
+
+        match self.message_log.react(id, &emoji, &nick, reacted) {
+            Ok(Some(sent)) => {
+                let event = ReactionsEvent {
+                    id,
+                    reactions: reactions(&sent),
+                };
+                self.send_to_recipients(&sent, &nick, ServerMessage::Reactions(event))
+                    .await;
+            }
+            Ok(None) => {}
+            Err(e) => self.send_to_self(format!("Could not react to message {}: {}", id, e)),
+        }
+    }
+
+    async fn handle_status(&self, status_message: StatusMessage) {
+        if self.nickname.lock().await.is_none() {
+            self.send_to_self(String::from(
+                "Please enter your nickname: /nick <your_nickname>",
+            ));
+            return;
+        }
+        let message = status_message
+            .message
+            .filter(|message| !message.trim().is_empty());
+        if message
+            .as_ref()
+            .is_some_and(|message| message.chars().count() > MAX_STATUS_LEN)
+        {
+            self.send_to_self(format!(
+                "Status is too long, at most {} characters",
+                MAX_STATUS_LEN
+            ));
+            return;
+        }
+
+        let changed = self
+            .set_presence(Presence {
+                status: status_message.status,
+                message,
+                automatic: false,
+            })
+            .await;
+        if !changed {
+            self.send_to_self(String::from(
+                "Status of this connection is set, the others show you as more available",
+            ));
+        }
+    }
+
+    // Sets the status of this connection, true if that changed the status of the nickname
+    // and everyone was told.
+    async fn set_presence(&self, presence: Presence) -> bool {
+        let Some(nick) = self.nickname.lock().await.clone() else {
+            *self.presence.lock().unwrap() = presence;
+            return false;
+        };
+
+        let clients = self.clients.lock().await;
+        let status_of = |clients: &HashMap<String, Vec<Client>>| {
+            clients
+                .get(&nick)
+                .and_then(|connections| user_status(&nick, connections))
+        };
+        let before = status_of(&clients);
+        *self.presence.lock().unwrap() = presence;
+        let after = status_of(&clients);
+        drop(clients);
+
+        match after {
+            Some(status) if after != before => {
+                let message = ServerMessage::Status(status);
+                Self::brodcast_message(&nick, message, Arc::clone(&self.clients), true).await;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    async fn handle_ignore(&self, ignore_message: IgnoreMessage) {
+        let Some(owner) = self.nickname.lock().await.clone() else {
+            self.send_to_self(String::from(
+                "Please enter your nickname: /nick <your_nickname>",
+            ));
+            return;
+        };
+        let Some(nick) = ignore_message.nick else {
+            let mut ignored: Vec<String> = self.ignored.lock().unwrap().iter().cloned().collect();
+            ignored.sort();
+            if ignored.is_empty() {
+                self.send_to_self(String::from("You are not ignoring anyone"));
+            } else {
+                self.send_to_self(format!("You are ignoring: {}", ignored.join(", ")));
+            }
+            return;
+        };
+
+        if nick == owner {
+            self.send_to_self(String::from("You can't ignore yourself"));
+            return;
+        }
+        if !is_valid_nickname(&nick) {
+            self.send_to_self(format!("Invalid nickname: {}", nick));
+            return;
+        }
+        if self.ignored.lock().unwrap().len() >= MAX_IGNORED {
+            self.send_to_self(format!("You can ignore at most {} users", MAX_IGNORED));
+            return;
+        }
+        if let (true, Some(ignore_list)) = (self.authenticated, &self.ignore_list) {
+            let (ignore_list, stored_owner, stored) =
+                (Arc::clone(ignore_list), owner.clone(), nick.clone());
+            if let Err(e) = run_blocking(move || ignore_list.ignore(&stored_owner, &stored)).await {
+                eprintln!("Could not store ignore list, error: {}", e);
+                self.send_to_self(String::from("Could not save your ignore list"));
+                return;
+            }
+        }
+
+        self.update_ignored(&owner, |ignored| {
+            ignored.insert(nick.clone());
+        })
+        .await;
+        self.send_to_self(format!(
+            "You are ignoring {}, their messages will not reach you",
+            nick
+        ));
+    }
+
+    async fn handle_unignore(&self, unignore_message: UnignoreMessage) {
+        let Some(owner) = self.nickname.lock().await.clone() else {
+            self.send_to_self(String::from(
+                "Please enter your nickname: /nick <your_nickname>",
+            ));
+            return;
+        };
+        let nick = unignore_message.nick;
+        if !self.ignored.lock().unwrap().contains(&nick) {
+            self.send_to_self(format!("You are not ignoring {}", nick));
+            return;
+        }
+        if let (true, Some(ignore_list)) = (self.authenticated, &self.ignore_list) {
+            let (ignore_list, stored_owner, stored) =
+                (Arc::clone(ignore_list), owner.clone(), nick.clone());
+            if let Err(e) = run_blocking(move || ignore_list.unignore(&stored_owner, &stored)).await
+            {
+                eprintln!("Could not store ignore list, error: {}", e);
+                self.send_to_self(String::from("Could not save your ignore list"));
+                return;
+            }
+        }
+
+        self.update_ignored(&owner, |ignored| {
+            ignored.remove(&nick);
+        })
+        .await;
+        self.send_to_self(format!("You are no longer ignoring {}", nick));
     }
 
-    fn parse_command(&self, input: String) -> Option<MessageType> {
-        match serde_json::from_str::<MessageType>(&input) {
-            Ok(message) => Some(message),
-            Err(_) => None,
+    // Every connection of a logged in user shares the same ignored nicknames.
+    async fn update_ignored<F>(&self, owner: &str, update: F)
+    where
+        F: Fn(&mut HashSet<String>),
+    {
+        if let Some(connections) = self.clients.lock().await.get(owner) {
+            for client in connections {
+                update(&mut client.ignored.lock().unwrap());
+            }
+        }
+    }
+
+    // Whether `receiver` ignores `sender`, asks the stored list if `receiver` is offline.
+    async fn ignores(&self, receiver: &str, sender: &str) -> bool {
+        if let Some(connections) = self.clients.lock().await.get(receiver) {
+            return connections
+                .iter()
+                .any(|client| client_ignores(client, sender));
+        }
+        self.ignore_list
+            .as_ref()
+            .is_some_and(|ignore_list| ignore_list.ignored(receiver).contains(sender))
+    }
+
+    async fn handle_users(&self) {
+        let mut users: Vec<UserStatus> = self
+            .clients
+            .lock()
+            .await
+            .iter()
+            .filter_map(|(nick, connections)| user_status(nick, connections))
+            .collect();
+        users.sort_by(|a, b| a.nick.cmp(&b.nick));
+
+        send_event(&self.outbox, ServerMessage::Users(UsersEvent { users }));
+    }
+
+    // Only changes of audience are relayed, repeated starts just keep the notification alive.
+    async fn handle_typing_start(&mut self, receiver: Option<String>) {
+        let Some(nick) = self.nickname.lock().await.clone() else {
+            return;
+        };
+        if receiver.as_ref() == Some(&nick) || self.is_muted().await {
+            return;
+        }
+
+        let typing = Typing {
+            receiver: receiver.clone(),
+            until: Instant::now() + TYPING_TIMEOUT,
+        };
+        let previous = self.typing.replace(typing);
+        match previous {
+            Some(previous) if previous.receiver == receiver => {}
+            Some(previous) => {
+                self.send_typing(&nick, previous.receiver, false).await;
+                self.send_typing(&nick, receiver, true).await;
+            }
+            None => self.send_typing(&nick, receiver, true).await,
+        }
+    }
+
+    async fn stop_typing(&mut self, nick: &str) {
+        if let Some(typing) = self.typing.take() {
+            self.send_typing(nick, typing.receiver, false).await;
+        }
+    }
+
+    async fn send_typing(&self, nick: &str, receiver: Option<String>, typing: bool) {
+        let event = ServerMessage::Typing(TypingEvent {
+            sender: nick.to_string(),
+            receiver: receiver.clone(),
+            typing,
+        });
+        match receiver {
+            Some(receiver) if !self.ignores(&receiver, nick).await => {
+                send_to_nick(&self.clients, &receiver, event).await;
+            }
+            Some(_) => {}
+            None => Self::brodcast_message(nick, event, Arc::clone(&self.clients), false).await,
+        }
+    }
+
+    // Tells everyone who got `sent` about a change made by `actor`, except the users
+    // ignoring them.
+    async fn send_to_recipients(&self, sent: &SentMessage, actor: &str, message: ServerMessage) {
+        match sent.receiver {
+            Some(ref receiver) => {
+                if !self.ignores(&sent.sender, actor).await {
+                    send_to_nick(&self.clients, &sent.sender, message.clone()).await;
+                }
+                if *receiver != sent.sender && !self.ignores(receiver, actor).await {
+                    send_to_nick(&self.clients, receiver, message).await;
+                }
+            }
+            None => Self::brodcast_message(actor, message, Arc::clone(&self.clients), true).await,
+        }
+    }
+
+    // Ids the user did not receive or already read are ignored, every connection of the
+    // receiver may report the same message.
+    async fn handle_read(&self, read_message: ReadMessage) {
+        let Some(nick) = self.nickname.lock().await.clone() else {
+            return;
+        };
+
+        let reader = self.identity(&nick);
+        for id in read_message.ids {
+            if let Some(sender) = self.receipts.read(id, &reader) {
+                let receipt = ReceiptEvent {
+                    id,
+                    receiver: nick.clone(),
+                    status: ReceiptStatus::Read,
+                };
+                send_to_nick(&self.clients, &sender, ServerMessage::Receipt(receipt)).await;
+            }
+        }
+    }
+
+    async fn handle_pending(&self) {
+        let Some(nick) = self.logged_in_nick().await else {
+            return;
+        };
+        let ignored = self.ignored.lock().unwrap().clone();
+        let pending: Vec<_> = self
+            .offline_queue
+            .as_ref()
+            .map(|offline_queue| offline_queue.list(&nick))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|message| !ignored.contains(&message.sender))
+            .collect();
+        if pending.is_empty() {
+            self.send_to_self(String::from("You have no pending messages"));
+            return;
+        }
+
+        let now = now();
+        let mut listing = format!("You have {} pending messages:", pending.len());
+        for message in pending {
+            listing.push_str(&format!(
+                "\n    {} ({}s ago): {}",
+                message.sender,
+                now.saturating_sub(message.sent_at),
+                message.message
+            ));
+        }
+        self.send_to_self(listing);
+    }
+
+    async fn handle_clear_pending(&self) {
+        let Some(nick) = self.logged_in_nick().await else {
+            return;
+        };
+        let cleared = match self.offline_queue {
+            Some(ref offline_queue) => {
+                let offline_queue = Arc::clone(offline_queue);
+                run_blocking(move || offline_queue.clear(&nick)).await
+            }
+            None => 0,
+        };
+        self.send_to_self(format!("Discarded {} pending messages", cleared));
+    }
+
+    // Pending messages belong to registered nicknames, guests have none.
+    async fn logged_in_nick(&self) -> Option<String> {
+        let nick = self.nickname.lock().await.clone();
+        if !self.authenticated || nick.is_none() {
+            self.send_to_self(String::from("You are not logged in"));
+            return None;
+        }
+        nick
+    }
+
+    async fn handle_op(&mut self, op_message: OpMessage) {
+        let Some(operator) = self.operator_nick().await else {
+            return;
+        };
+        // Guest nicknames can be taken by anyone once their holder leaves.
+        if !self.users.is_registered(&op_message.nick) {
+            self.send_to_self(format!(
+                "Only registered users can be operators, {} is not registered",
+                op_message.nick
+            ));
+            return;
+        }
+
+        match self.clients.lock().await.get(&op_message.nick) {
+            Some(connections) if op_message.nick != operator => {
+                for client in connections {
+                    let _ = client.control.send(Control::Op);
+                }
+                self.send_to_self(format!("{} is now an operator", op_message.nick));
+            }
+            Some(_) => self.send_to_self(String::from("You are already an operator")),
+            None => self.send_to_self(format!(
+                "Client with nickname: {} is not connected to chat",
+                op_message.nick
+            )),
+        }
+    }
+
+    async fn handle_kick(&mut self, kick_message: KickMessage) {
+        let Some(operator) = self.operator_nick().await else {
+            return;
+        };
+        if kick_message.nick == operator {
+            self.send_to_self(String::from("You can't kick yourself"));
+            return;
+        }
+
+        let reason = match kick_message.reason {
+            Some(reason) => format!("Kicked by {}: {}", operator, reason),
+            None => format!("Kicked by {}", operator),
+        };
+        if self
+            .kick(|nick, _| nick == kick_message.nick, &reason)
+            .await
+            .is_empty()
+        {
+            self.send_to_self(format!(
+                "Client with nickname: {} is not connected to chat",
+                kick_message.nick
+            ));
+            return;
+        }
+
+        Self::brodcast_message(
+            &operator,
+            notice(format!("{} left the chat. {}", kick_message.nick, reason)),
+            Arc::clone(&self.clients),
+            false,
+        )
+        .await;
+        self.send_to_self(format!("{} was kicked", kick_message.nick));
+    }
+
+    async fn handle_ban(&mut self, ban_message: BanMessage) {
+        let Some(operator) = self.operator_nick().await else {
+            return;
+        };
+        let Some(duration) = self.parse_duration_arg(ban_message.duration.as_deref()) else {
+            return;
+        };
+
+        let target = BanTarget::parse(&ban_message.target);
+        let bans_self = match &target {
+            BanTarget::Nick(nick) => *nick == operator,
+            BanTarget::Ip(ip) => self.ip == Some(*ip),
+        };
+        if bans_self {
+            self.send_to_self(String::from("You can't ban yourself"));
+            return;
+        }
+
+        let bans = Arc::clone(self.moderation.bans());
+        let banned = target.clone();
+        if let Err(e) = run_blocking(move || bans.ban(banned, duration)).await {
+            eprintln!("Could not store ban of {}, error: {}", target, e);
+            self.send_to_self(String::from("Could not store ban, please try again"));
+            return;
+        }
+
+        let reason = format!("Banned by {}", operator);
+        let kicked = self
+            .kick(
+                |nick, client| match &target {
+                    BanTarget::Nick(banned) => nick == banned,
+                    BanTarget::Ip(banned) => client.ip == Some(*banned),
+                },
+                &reason,
+            )
+            .await;
+        for nick in kicked {
+            Self::brodcast_message(
+                &operator,
+                notice(format!("{} left the chat. {}", nick, reason)),
+                Arc::clone(&self.clients),
+                false,
+            )
+            .await;
+        }
+
+        self.send_to_self(format!(
+            "{} is banned {}",
+            ban_message.target,
+            describe_duration(ban_message.duration.as_deref())
+        ));
+    }
+
+    async fn handle_mute(&mut self, mute_message: MuteMessage) {
+        let Some(operator) = self.operator_nick().await else {
+            return;
+        };
+        let Some(duration) = self.parse_duration_arg(mute_message.duration.as_deref()) else {
+            return;
+        };
+        if mute_message.nick == operator {
+            self.send_to_self(String::from("You can't mute yourself"));
+            return;
+        }
+
+        self.moderation.mute(&mute_message.nick, duration);
+
+        let description = describe_duration(mute_message.duration.as_deref());
+        send_to_nick(
+            &self.clients,
+            &mute_message.nick,
+            notice(format!("You are muted by {} {}", operator, description)),
+        )
+        .await;
+        self.send_to_self(format!("{} is muted {}", mute_message.nick, description));
+    }
+
+    // Nickname of this session if it is an operator, tells the user otherwise.
+    async fn operator_nick(&self) -> Option<String> {
+        let nick = self.nickname.lock().await.clone();
+        if !self.operator || nick.is_none() {
+            self.send_to_self(String::from("Only operators can use this command"));
+            return None;
+        }
+        nick
+    }
+
+    // Outer None if the duration is invalid, inner None if there is no duration.
+    fn parse_duration_arg(&self, duration: Option<&str>) -> Option<Option<Duration>> {
+        match duration.map(parse_duration) {
+            Some(None) => {
+                self.send_to_self(String::from(
+                    "Invalid duration, use seconds or a number with s, m, h or d suffix",
+                ));
+                None
+            }
+            parsed => Some(parsed.flatten()),
+        }
+    }
+
+    // Asks the sessions matching `filter` to close their connection, returns their nicknames.
+    async fn kick<F>(&self, filter: F, reason: &str) -> Vec<String>
+    where
+        F: Fn(&str, &Client) -> bool,
+    {
+        let clients = self.clients.lock().await;
+        let mut kicked = Vec::new();
+        for (nick, connections) in clients.iter() {
+            for client in connections.iter().filter(|client| filter(nick, client)) {
+                let sent = client
+                    .control
+                    .send(Control::Kick(reason.to_string()))
+                    .is_ok();
+                if sent && !kicked.contains(nick) {
+                    kicked.push(nick.clone());
+                }
+            }
+        }
+        kicked
+    }
+
+    // Closes the connection giving the client a moment to answer the close frame,
+    // so it gets to read what was sent before.
+    async fn close(&mut self, code: CloseCode, mut reason: String) {
+        if reason.len() > MAX_CLOSE_REASON_LEN {
+            let mut end = MAX_CLOSE_REASON_LEN;
+            while !reason.is_char_boundary(end) {
+                end -= 1;
+            }
+            reason.truncate(end);
+        }
+
+        let close_frame = CloseFrame {
+            code,
+            reason: reason.into(),
+        };
+        self.outbox.send(Outgoing::Close(close_frame));
+        self.handle_close_message().await;
+
+        let _ = tokio::time::timeout(CLOSE_TIMEOUT, async {
+            while let Some(Ok(message)) = self.socket_read_half.next().await {
+                if message.is_close() {
+                    break;
+                }
+            }
+        })
+        .await;
+    }
+
+    fn check_nickname_available(&self, nick: &str) -> Option<String> {
+        if !is_valid_nickname(nick) {
+            return Some(format!(
+                "Invalid nickname: {}, it must have 1 to {} characters without spaces and ':'",
+                nick, MAX_NICKNAME_LEN
+            ));
+        }
+
+        if self.moderation.is_nick_banned(nick) {
+            return Some(format!("Nickname {} is banned", nick));
+        }
+
+        if is_mention_everyone(nick) {
+            return Some(format!("Nickname {} is reserved", nick));
+        }
+
+        None
+    }
+
+    async fn is_muted(&self) -> bool {
+        if self.muted_until.is_some_and(|until| until > Instant::now()) {
+            return true;
+        }
+
+        match *self.nickname.lock().await {
+            Some(ref nick) => self.moderation.is_muted(nick),
+            None => false,
+        }
+    }
+
+    // Checks and takes `nick` under one lock. False if the nickname is held by another
+    // identity, only the sessions of one account share a nickname.
+    async fn join(&self, nick: String, identity: Identity) -> bool {
+        {
+            let mut clients = self.clients.lock().await;
+            let connections = clients.entry(nick.clone()).or_default();
+            if connections.iter().any(|client| client.identity != identity) {
+                return false;
+            }
+            connections.push(Client {
+                outbox: self.outbox.clone(),
+                control: self.control_tx.clone(),
+                ip: self.ip,
+                identity,
+                presence: Arc::clone(&self.presence),
+                ignored: Arc::clone(&self.ignored),
+            });
+        }
+        *self.nickname.lock().await = Some(nick);
+        true
+    }
+
+    // Messages and receipts belong to the identity, a guest nickname can be taken by
+    // someone else once it is free.
+    fn identity(&self, nick: &str) -> Identity {
+        if self.authenticated {
+            Identity::Account(nick.to_string())
+        } else {
+            self.guest.clone()
+        }
+    }
+
+    // Hands the entry of this connection over to the account it registered.
+    async fn set_identity(&self, nick: &str, identity: Identity) {
+        if let Some(connections) = self.clients.lock().await.get_mut(nick) {
+            for client in connections
+                .iter_mut()
+                .filter(|client| client.outbox.same_channel(&self.outbox))
+            {
+                client.identity = identity.clone();
+            }
+        }
+    }
+
+    // Removes this connection, the other connections of `nick` stay.
+    async fn remove_client(&mut self, nick: &str) {
+        self.stop_typing(nick).await;
+        let mut clients = self.clients.lock().await;
+        if let Some(connections) = clients.get_mut(nick) {
+            connections.retain(|client| !client.outbox.same_channel(&self.outbox));
+            if connections.is_empty() {
+                clients.remove(nick);
+            }
+        }
+    }
+
+    async fn leave(&mut self) {
+        let nick = self.nickname.lock().await.take();
+        if let Some(nick) = nick {
+            self.remove_client(&nick).await;
+        }
+        self.ignored.lock().unwrap().clear();
+        self.authenticated = false;
+        self.operator = false;
+    }
+
+    async fn set_logged_in(&mut self, nick: &str) {
+        self.authenticated = true;
+        self.operator = self.moderation.is_operator(nick);
+
+        let Some(ref ignore_list) = self.ignore_list else {
+            return;
+        };
+        // Nicknames ignored before registering are kept.
+        let kept: Vec<String> = self.ignored.lock().unwrap().iter().cloned().collect();
+        if !kept.is_empty() {
+            let (ignore_list, owner) = (Arc::clone(ignore_list), nick.to_string());
+            if let Err(e) = run_blocking(move || ignore_list.ignore_all(&owner, &kept)).await {
+                eprintln!("Could not store ignore list, error: {}", e);
+            }
+        }
+        let stored = ignore_list.ignored(nick);
+        self.ignored.lock().unwrap().extend(stored);
+    }
+
+    // Sends to everyone but the connections ignoring the sender, to the sender too if
+    // `include_sender`.
+    async fn brodcast_message(
+        sender_nick: &str,
+        message: ServerMessage,
+        clients: Clients,
+        include_sender: bool,
+    ) {
+        for (nick, connections) in clients.lock().await.iter() {
+            if nick == sender_nick && !include_sender {
+                continue;
+            }
+
+            for client in connections {
+                if !client_ignores(client, sender_nick) {
+                    send_event(&client.outbox, message.clone());
+                }
+            }
+        }
+    }
+
+    async fn handle_close_message(&mut self) {
+        let nick = self.nickname.lock().await.clone();
+        if let Some(nick) = nick {
+            self.remove_client(&nick).await;
+        }
+        println!("Client disconnected");
+    }
+
+    fn send_to_self(&self, message: String) {
+        send_to(&self.outbox, message);
+    }
+
+    // The first data frame decides the codec if the handshake did not.
+    fn parse_command(&mut self, input: &Message) -> Result<MessageType, ErrorEvent> {
+        let codec = match self.codec {
+            Some(codec) => codec,
+            None => {
+                let codec = Codec::detect(input).unwrap_or(Codec::Json);
+                self.codec = Some(codec);
+                self.outbox.send(Outgoing::Codec(codec));
+                codec
+            }
+        };
+        codec.decode_command(input)
+    }
+}
+
+// Runs a change of a store that writes to disk without blocking the async workers.
+async fn run_blocking<T, F>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+fn in_use(nick: &str) -> String {
+    format!("Nickname {} is already in use", nick)
+}
+
+fn notice(message: String) -> ServerMessage {
+    ServerMessage::Notice(NoticeEvent { message })
+}
+
+fn send_to(outbox: &Outbox, message: String) {
+    send_event(outbox, notice(message));
+}
+
+// Sends to every connection of `nick`, returns who holds the nickname, None if it is not
+// connected.
+async fn send_to_nick(clients: &Clients, nick: &str, message: ServerMessage) -> Option<Identity> {
+    let clients = clients.lock().await;
+    let connections = clients.get(nick)?;
+    for client in connections {
+        send_event(&client.outbox, message.clone());
+    }
+    connections.first().map(|client| client.identity.clone())
+}
+
+// The most available status among the connections of `nick`, None without connections.
+fn user_status(nick: &str, connections: &[Client]) -> Option<UserStatus> {
+    connections
+        .iter()
+        .map(|client| client.presence.lock().unwrap().clone())
+        .min_by_key(|presence| match presence.status {
+            Status::Online => 0,
+            Status::Dnd => 1,
+            Status::Away => 2,
+        })
+        .map(|presence| UserStatus {
+            nick: nick.to_string(),
+            status: presence.status,
+            message: presence.message,
+        })
+}
+
+fn reactions(sent: &SentMessage) -> Vec<Reaction> {
+    sent.reactions
+        .iter()
+        .map(|(emoji, nicks)| Reaction {
+            emoji: emoji.clone(),
+            count: nicks.len(),
+            nicks: nicks.iter().cloned().collect(),
+        })
+        .collect()
+}
+
+fn quote(id: u64, sent: &SentMessage) -> Quote {
+    Quote {
+        id,
+        sender: sent.sender.clone(),
+        message: sent.message.chars().take(MAX_QUOTE_LEN).collect(),
+    }
+}
+
+// Notifies the mentioned users but not the sender, everyone if "here" or "all" is mentioned.
+async fn send_mentions(clients: &Clients, mentions: &[String], mention: MentionEvent) {
+    let everyone = mentions.iter().any(|name| is_mention_everyone(name));
+    for (nick, connections) in clients.lock().await.iter() {
+        if *nick == mention.sender || !(everyone || mentions.contains(nick)) {
+            continue;
+        }
+
+        // Do not disturb only silences the notification, the message itself was sent.
+        for client in connections {
+            if client.presence.lock().unwrap().status != Status::Dnd
+                && !client_ignores(client, &mention.sender)
+            {
+                send_event(&client.outbox, ServerMessage::Mention(mention.clone()));
+            }
+        }
+    }
+}
+
+// The same for every failed delivery, the sender is not told they are ignored.
+fn undelivered(receiver: &str) -> String {
+    format!("Could not deliver message to {}", receiver)
+}
+
+fn client_ignores(client: &Client, nick: &str) -> bool {
+    client.ignored.lock().unwrap().contains(nick)
+}
+
+async fn send_receipt(clients: &Clients, event: &PrivateEvent, status: ReceiptStatus) {
+    let receipt = ReceiptEvent {
+        id: event.id,
+        receiver: event.receiver.clone(),
+        status,
+    };
+    send_to_nick(clients, &event.sender, ServerMessage::Receipt(receipt)).await;
+}
+
+// Sending only fails once the writer task is gone, the session is closing then.
+fn send_event(outbox: &Outbox, message: ServerMessage) {
+    outbox.send(Outgoing::Message(message));
+}
+
+// Writes queued messages until the close frame or until every outbox is dropped.
+fn spawn_writer<S>(mut write_half: SplitSink<WebSocketStream<S>, Message>, codec: Codec) -> Outbox
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (sender, mut queue) = channel::<Outgoing>(OUTBOX_CAPACITY);
+    let outbox = Outbox {
+        queue: sender,
+        overflow: Arc::new(Notify::new()),
+    };
+    let overflow = Arc::clone(&outbox.overflow);
+    tokio::spawn(async move {
+        let mut codec = codec;
+        loop {
+            let outgoing = tokio::select! {
+                outgoing = queue.recv() => match outgoing {
+                    Some(outgoing) => outgoing,
+                    None => return,
+                },
+                _ = overflow.notified() => break,
+            };
+            let message = match outgoing {
+                Outgoing::Message(message) => match codec.encode(&message) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        eprintln!("Could not encode message, error: {}", e);
+                        continue;
+                    }
+                },
+                Outgoing::Close(close_frame) => Message::Close(Some(close_frame)),
+                Outgoing::Codec(new_codec) => {
+                    codec = new_codec;
+                    continue;
+                }
+            };
+
+            let is_close = message.is_close();
+            tokio::select! {
+                sent = write_half.send(message) => {
+                    if let Err(e) = sent {
+                        eprintln!("Could not send message, error: {}", e);
+                        return;
+                    }
+                }
+                _ = overflow.notified() => break,
+            }
+            if is_close {
+                return;
+            }
+        }
+        eprintln!("Client does not read its messages, disconnecting");
+    });
+    outbox
+}
+
+fn describe_duration(duration: Option<&str>) -> String {
+    match duration {
+        Some(duration) => format!("for {}", duration),
+        None => String::from("until further notice"),
+    }
+}
+
+fn check_message_len(message: &str) -> Option<String> {
+    let len = message.chars().count();
+    if len > MAX_MESSAGE_LEN {
+        return Some(format!(
+            "Message is too long, {} characters, the limit is {}",
+            len, MAX_MESSAGE_LEN
+        ));
+    }
+    None
+}
+
+fn is_valid_nickname(nick: &str) -> bool {
+    !nick.is_empty()
+        && nick.chars().count() <= MAX_NICKNAME_LEN
+        && !nick.chars().any(|c| c.is_whitespace() || c == ':')
+}
+
+fn is_valid_emoji(emoji: &str) -> bool {
+    !emoji.is_empty()
+        && emoji.chars().count() <= MAX_EMOJI_LEN
+        && !emoji.chars().any(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::message_log::MessageLog;
+    use crate::ws::moderation::BanList;
+    use crate::ws::rate_limit::RateLimit;
+    use crate::ws::receipts::{MessageIds, ReadReceipts};
+    use crate::ws::test_util::TempDir;
+    use crate::ws::ws_message::{HelpMessage, QuitMessage, TypingStartMessage, UsersMessage};
+    use crate::ws::ws_server::default_websocket_config;
+    use tokio::io::{duplex, DuplexStream};
+    use tokio_tungstenite::tungstenite::protocol::Role;
+
+    const PASSWORD: &str = "password1";
+    // Only reached when the server does not send what a test waits for.
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    // Accounts without password hashing, every one has PASSWORD.
+    struct Accounts(std::sync::Mutex<HashSet<String>>);
+
+    impl UserStore for Accounts {
+        fn is_registered(&self, nick: &str) -> bool {
+            self.0.lock().unwrap().contains(nick)
+        }
+
+        fn register(&self, nick: &str, _password: &str) -> Result<(), UserStoreError> {
+            if !self.0.lock().unwrap().insert(nick.to_string()) {
+                return Err(UserStoreError::AlreadyRegistered);
+            }
+            Ok(())
+        }
+
+        fn verify(&self, nick: &str, password: &str) -> bool {
+            password == PASSWORD && self.is_registered(nick)
+        }
+    }
+
+    // Chat endpoint with the accounts "alice", "bob" and the operator "admin".
+    struct TestChat {
+        state: ChatState,
+        _dir: TempDir,
+    }
+
+    impl TestChat {
+        fn new(name: &str) -> Self {
+            let dir = TempDir::new(name);
+            let accounts = ["alice", "bob", "admin"].map(String::from);
+            let bans = BanList::new(&dir.join("bans.txt")).unwrap();
+            let limit = RateLimit::new(1000, 1000.0);
+            let state = ChatState {
+                clients: Arc::new(Mutex::new(HashMap::new())),
+                users: Arc::new(Accounts(std::sync::Mutex::new(HashSet::from(accounts)))),
+                moderation: Arc::new(Moderation::new(Arc::new(bans), vec![String::from("admin")])),
+                offline_queue: Some(Arc::new(
+                    OfflineQueue::new(&dir.join("messages.txt"), Duration::from_secs(3600))
+                        .unwrap(),
+                )),
+                ignore_list: Some(Arc::new(IgnoreList::new(&dir.join("ignores.txt")).unwrap())),
+                message_ids: Arc::new(MessageIds::new()),
+                receipts: Arc::new(ReadReceipts::new()),
+                message_log: Arc::new(MessageLog::new()),
+                edit_window: Duration::from_secs(60),
+                away_after: Duration::ZERO,
+                rate_limits: RateLimits {
+                    chat: limit,
+                    private: limit,
+                    command: limit,
+                    typing: limit,
+                    global: limit,
+                    ..RateLimits::default()
+                },
+                global_limit: Arc::new(std::sync::Mutex::new(TokenBucket::new(limit))),
+                login_limiter: Arc::new(IpRateLimiter::new(limit)),
+            };
+            Self { state, _dir: dir }
+        }
+
+        // Connection that negotiated JSON, past the usage message.
+        async fn connect(&self) -> TestClient {
+            let (client, server) = duplex(256 * 1024);
+            let state = self.state.clone();
+            tokio::spawn(async move {
+                let config = Some(default_websocket_config());
+                let ws = WebSocketStream::from_raw_socket(server, Role::Server, config).await;
+                let ip = Some(IpAddr::from([127, 0, 0, 1]));
+                if let Some(mut session) =
+                    WsSession::new(ws, state, None, ip, Some(Codec::Json)).await
+                {
+                    session.handle_ws_connection().await;
+                }
+            });
+
+            let ws = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+            let mut client = TestClient { ws };
+            client.notice().await;
+            client
+        }
+
+        async fn guest(&self, name: &str) -> TestClient {
+            let mut client = self.connect().await;
+            client.send(nick(name)).await;
+            let hello = format!("Hello {}, now you can send messages", name);
+            client.until_notice(&hello).await;
+            client
+        }
+
+        async fn account(&self, name: &str) -> TestClient {
+            let mut client = self.connect().await;
+            client
+                .send(MessageType::Login(LoginMessage {
+                    nick: name.to_string(),
+                    password: PASSWORD.to_string(),
+                }))
+                .await;
+            client
+                .until_notice(&format!("Hello {}, you are logged in", name))
+                .await;
+            client
+        }
+    }
+
+    struct TestClient {
+        ws: WebSocketStream<DuplexStream>,
+    }
+
+    impl TestClient {
+        async fn send(&mut self, command: MessageType) {
+            self.send_frame(Codec::Json.encode(&command).unwrap()).await;
+        }
+
+        async fn send_frame(&mut self, frame: Message) {
+            self.ws.send(frame).await.unwrap();
+        }
+
+        async fn recv(&mut self) -> ServerMessage {
+            match self.next_frame().await {
+                message @ Message::Text(_) => Codec::Json.decode(&message).unwrap(),
+                other => panic!("Expected a message, got {:?}", other),
+            }
+        }
+
+        async fn next_frame(&mut self) -> Message {
+            match tokio::time::timeout(TIMEOUT, self.ws.next()).await {
+                Ok(Some(Ok(frame))) => frame,
+                other => panic!("Expected a frame, got {:?}", other),
+            }
+        }
+
+        // Code of the close frame, skipping the messages before it.
+        async fn close_code(&mut self) -> CloseCode {
+            loop {
+                if let Message::Close(frame) = self.next_frame().await {
+                    return frame.expect("Close frame without a code").code;
+                }
+            }
+        }
+
+        async fn notice(&mut self) -> String {
+            match self.recv().await {
+                ServerMessage::Notice(notice) => notice.message,
+                other => panic!("Expected a notice, got {:?}", other),
+            }
+        }
+
+        // Skips messages until `find` picks one.
+        async fn until<T, F>(&mut self, mut find: F) -> T
+        where
+            F: FnMut(ServerMessage) -> Option<T>,
+        {
+            loop {
+                if let Some(found) = find(self.recv().await) {
+                    return found;
+                }
+            }
+        }
+
+        async fn until_notice(&mut self, text: &str) {
+            self.until(|message| match message {
+                ServerMessage::Notice(notice) if notice.message == text => Some(()),
+                _ => None,
+            })
+            .await
+        }
+
+        // Id of the next chat message of `sender`.
+        async fn until_chat(&mut self, sender: &str) -> u64 {
+            self.until(|message| match message {
+                ServerMessage::Chat(chat) if chat.sender == sender => Some(chat.id),
+                _ => None,
+            })
+            .await
+        }
+
+        async fn until_status(&mut self, nick: &str) -> UserStatus {
+            self.until(|message| match message {
+                ServerMessage::Status(status) if status.nick == nick => Some(status),
+                _ => None,
+            })
+            .await
+        }
+
+        // Id of the next private message from `sender`, or its echo.
+        async fn until_private(&mut self, sender: &str) -> u64 {
+            self.until(|message| match message {
+                ServerMessage::Private(private) if private.sender == sender => Some(private.id),
+                _ => None,
+            })
+            .await
+        }
+
+        // Messages sent to this connection so far. The session handles its commands in
+        // order, so whatever another session did before its own reply is in here.
+        async fn received(&mut self) -> Vec<ServerMessage> {
+            self.send(MessageType::Users(UsersMessage {})).await;
+            let mut received = Vec::new();
+            loop {
+                match self.recv().await {
+                    ServerMessage::Users(_) => return received,
+                    message => received.push(message),
+                }
+            }
+        }
+    }
+
+    fn nick(nick: &str) -> MessageType {
+        MessageType::Nick(NickMessage {
+            nick: nick.to_string(),
+        })
+    }
+
+    fn chat(message: &str) -> MessageType {
+        MessageType::Chat(ChatMessage {
+            message: message.to_string(),
+            reply_to: None,
+        })
+    }
+
+    fn private(receiver: &str, message: &str) -> MessageType {
+        MessageType::Private(PrivateMessage {
+            receiver: receiver.to_string(),
+            message: message.to_string(),
+        })
+    }
+
+    fn typing_start(receiver: Option<&str>) -> MessageType {
+        MessageType::TypingStart(TypingStartMessage {
+            receiver: receiver.map(String::from),
+        })
+    }
+
+    fn status(status: Status, message: Option<&str>) -> MessageType {
+        MessageType::Status(StatusMessage {
+            status,
+            message: message.map(String::from),
+        })
+    }
+
+    fn edit(id: u64, message: &str) -> MessageType {
+        MessageType::Edit(EditMessage {
+            id,
+            message: message.to_string(),
+        })
+    }
+
+    fn notices(received: &[ServerMessage]) -> Vec<&str> {
+        received
+            .iter()
+            .filter_map(|message| match message {
+                ServerMessage::Notice(notice) => Some(notice.message.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // The typing notifications, chat and private messages, in order.
+    fn conversation(received: &[ServerMessage]) -> Vec<String> {
+        received
+            .iter()
+            .filter_map(|message| match message {
+                ServerMessage::Typing(typing) => Some(format!(
+                    "{} {} {}",
+                    typing.sender,
+                    if typing.typing { "starts" } else { "stops" },
+                    typing.receiver.as_deref().unwrap_or("chat")
+                )),
+                ServerMessage::Chat(chat) => Some(format!("chat {}", chat.message)),
+                ServerMessage::Private(private) => Some(format!("private {}", private.message)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn receipts(received: &[ServerMessage]) -> Vec<(u64, ReceiptStatus)> {
+        received
+            .iter()
+            .filter_map(|message| match message {
+                ServerMessage::Receipt(receipt) => Some((receipt.id, receipt.status)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn from_sender(received: &[ServerMessage], nick: &str) -> usize {
+        received
+            .iter()
+            .filter(|message| match message {
+                ServerMessage::Chat(ChatEvent { sender, .. })
+                | ServerMessage::Private(PrivateEvent { sender, .. })
+                | ServerMessage::Mention(MentionEvent { sender, .. })
+                | ServerMessage::Typing(TypingEvent { sender, .. }) => sender == nick,
+                _ => false,
+            })
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_ignorers_are_skipped() {
+        let server = TestChat::new("session_ignore");
+        let mut mallory = server.guest("mallory").await;
+        let mut bob = server.account("bob").await;
+        let mut carol = server.guest("carol").await;
+        bob.send(MessageType::Ignore(IgnoreMessage {
+            nick: Some(String::from("mallory")),
+        }))
+        .await;
+        bob.until_notice("You are ignoring mallory, their messages will not reach you")
+            .await;
+
+        mallory.send(typing_start(None)).await;
+        mallory.send(chat("hi @bob and @carol")).await;
+        mallory.until_chat("mallory").await;
+        mallory.send(typing_start(Some("bob"))).await;
+        mallory.send(private("bob", "psst")).await;
+        mallory.until_notice(&undelivered("bob")).await;
+        mallory.received().await;
+
+        let received = carol.received().await;
+        // Typing start and stop, the chat message and the mention.
+        assert_eq!(from_sender(&received, "mallory"), 4);
+        assert_eq!(from_sender(&bob.received().await, "mallory"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_auto_away_until_next_message() {
+        let mut server = TestChat::new("session_auto_away");
+        server.state.away_after = Duration::from_millis(100);
+        let mut carol = server.guest("carol").await;
+        let mut dave = server.guest("dave").await;
+
+        assert_eq!(dave.until_status("carol").await.status, Status::Away);
+        carol.send(chat("back")).await;
+        assert_eq!(dave.until_status("carol").await.status, Status::Online);
+    }
+
+    #[tokio::test]
+    async fn test_away_reply_once_per_private_message() {
+        let server = TestChat::new("session_auto_reply");
+        let mut bob = server.account("bob").await;
+        let mut bob_phone = server.account("bob").await;
+        bob.send(status(Status::Away, Some("lunch"))).await;
+        bob.until_notice("Status of this connection is set, the others show you as more available")
+            .await;
+        bob_phone.send(status(Status::Away, Some("lunch"))).await;
+        bob_phone.until_status("bob").await;
+
+        let mut carol = server.guest("carol").await;
+        carol.send(private("bob", "lunch?")).await;
+        let received = carol.received().await;
+        let replies: Vec<_> = notices(&received)
+            .into_iter()
+            .filter(|notice| notice.starts_with("bob is"))
+            .collect();
+        assert_eq!(replies, vec!["bob is away: lunch"]);
+
+        bob.send(status(Status::Dnd, None)).await;
+        bob.until_status("bob").await;
+        carol.send(private("bob", "now?")).await;
+        let received = carol.received().await;
+        assert!(notices(&received).contains(&"bob does not want to be disturbed"));
+    }
+
+    #[tokio::test]
+    async fn test_dnd_suppresses_mentions() {
+        let server = TestChat::new("session_dnd");
+        let mut carol = server.guest("carol").await;
+        let mut dave = server.guest("dave").await;
+        dave.send(status(Status::Dnd, None)).await;
+        dave.until_status("dave").await;
+
+        carol.send(chat("@dave look")).await;
+        carol.until_chat("carol").await;
+        carol.received().await;
+
+        let received = dave.received().await;
+        assert_eq!(from_sender(&received, "carol"), 1);
+        assert!(matches!(received[..], [ServerMessage::Chat(_)]));
+    }
+
+    #[tokio::test]
+    async fn test_users_are_listed_in_order_with_status() {
+        let server = TestChat::new("session_users");
+        let mut carol = server.guest("carol").await;
+        let mut bob = server.account("bob").await;
+        let _bob_phone = server.account("bob").await;
+        let mut alice = server.account("alice").await;
+        bob.send(status(Status::Away, Some("lunch"))).await;
+        bob.until_notice("Status of this connection is set, the others show you as more available")
+            .await;
+        alice.send(status(Status::Dnd, Some("focus"))).await;
+        alice.until_status("alice").await;
+
+        carol.send(MessageType::Users(UsersMessage {})).await;
+        let users = carol
+            .until(|message| match message {
+                ServerMessage::Users(users) => Some(users.users),
+                _ => None,
+            })
+            .await;
+        let user = |nick: &str, status, message: Option<&str>| UserStatus {
+            nick: nick.to_string(),
+            status,
+            message: message.map(String::from),
+        };
+        assert_eq!(
+            users,
+            vec![
+                user("alice", Status::Dnd, Some("focus")),
+                // The other connection of bob is still online.
+                user("bob", Status::Online, None),
+                user("carol", Status::Online, None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_typing_follows_audience() {
+        let server = TestChat::new("session_typing");
+        let mut carol = server.guest("carol").await;
+        let mut dave = server.guest("dave").await;
+        let mut erin = server.guest("erin").await;
+        let mut bob = server.account("bob").await;
+        bob.send(MessageType::Ignore(IgnoreMessage {
+            nick: Some(String::from("carol")),
+        }))
+        .await;
+        bob.until_notice("You are ignoring carol, their messages will not reach you")
+            .await;
+
+        // Repeated starts only keep the notification alive.
+        carol.send(typing_start(None)).await;
+        carol.send(typing_start(None)).await;
+        carol.send(typing_start(Some("dave"))).await;
+        carol.send(private("dave", "hi")).await;
+        carol.send(typing_start(Some("bob"))).await;
+        carol.send(typing_start(None)).await;
+        carol.send(chat("hello")).await;
+        carol.until_chat("carol").await;
+        carol.received().await;
+
+        assert_eq!(
+            conversation(&dave.received().await),
+            vec![
+                "carol starts chat",
+                "carol stops chat",
+                "carol starts dave",
+                "carol stops dave",
+                "private hi",
+                "carol starts chat",
+                "carol stops chat",
+                "chat hello",
+            ]
+        );
+        assert_eq!(
+            conversation(&erin.received().await),
+            vec![
+                "carol starts chat",
+                "carol stops chat",
+                "carol starts chat",
+                "carol stops chat",
+                "chat hello",
+            ]
+        );
+        assert!(conversation(&bob.received().await).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_typing_expires() {
+        let server = TestChat::new("session_typing_expires");
+        let mut carol = server.guest("carol").await;
+        let mut dave = server.guest("dave").await;
+        let started = tokio::time::Instant::now();
+
+        carol.send(typing_start(None)).await;
+        let typing = |message| match message {
+            ServerMessage::Typing(typing) => Some(typing.typing),
+            _ => None,
+        };
+        assert!(dave.until(typing).await);
+        assert!(!dave.until(typing).await);
+        assert!(started.elapsed() >= TYPING_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_only_author_or_operator_changes_message() {
+        let server = TestChat::new("session_author");
+        let mut carol = server.guest("carol").await;
+        let mut dave = server.guest("dave").await;
+        dave.send(chat("mine")).await;
+        let id = dave.until_chat("dave").await;
+        dave.send(edit(id, "still mine")).await;
+        assert!(matches!(carol.recv().await, ServerMessage::Chat(_)));
+        assert!(matches!(carol.recv().await, ServerMessage::Edit(_)));
+
+        // An account registered under the nickname the guest left is someone else.
+        dave.send(MessageType::Quit(QuitMessage {})).await;
+        dave.until_notice("You left the chat.").await;
+        let mut account = server.connect().await;
+        account
+            .send(MessageType::Register(RegisterMessage {
+                nick: String::from("dave"),
+                password: PASSWORD.to_string(),
+            }))
+            .await;
+        account
+            .until_notice("Nickname dave registered, you are logged in")
+            .await;
+        account.send(edit(id, "taken over")).await;
+        account
+            .until_notice("You can only change your own messages")
+            .await;
+
+        let mut admin = server.account("admin").await;
+        admin.send(MessageType::Delete(DeleteMessage { id })).await;
+        let deleted = carol
+            .until(|message| match message {
+                ServerMessage::Delete(delete) => Some(delete),
+                _ => None,
+            })
+            .await;
+        assert_eq!(
+            deleted,
+            DeleteEvent {
+                id,
+                deleted_by: String::from("admin"),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_edit_window() {
+        let server = TestChat::new("session_edit_window");
+        server.state.message_log.record(
+            1,
+            SentMessage {
+                sender: String::from("alice"),
+                author: Identity::Account(String::from("alice")),
+                receiver: None,
+                message: String::from("old"),
+                sent_at: now() - 120,
+                reply_to: None,
+                thread: None,
+                reactions: BTreeMap::new(),
+                mentions: Vec::new(),
+            },
+        );
+
+        let mut alice = server.account("alice").await;
+        alice.send(edit(1, "new")).await;
+        alice
+            .until_notice("Message 1 is older than 60s and can't be changed")
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_changes_reach_queued_messages() {
+        let server = TestChat::new("session_queued_changes");
+        let mut alice = server.account("alice").await;
+        alice.send(private("bob", "helo")).await;
+        let typo = alice.until_private("alice").await;
+        alice.send(private("bob", "oops")).await;
+        let oops = alice.until_private("alice").await;
+
+        alice.send(edit(typo, "hello")).await;
+        alice
+            .send(MessageType::Delete(DeleteMessage { id: oops }))
+            .await;
+        alice.received().await;
+
+        let mut bob = server.account("bob").await;
+        let received = bob.received().await;
+        assert!(notices(&received).contains(&"You have 1 messages sent while you were offline"));
+        assert_eq!(conversation(&received), vec!["private hello"]);
+    }
+
+    #[tokio::test]
+    async fn test_only_account_connections_share_nick() {
+        let server = TestChat::new("session_share_nick");
+        let _alice = server.account("alice").await;
+        let _alice_phone = server.account("alice").await;
+        let mut guest = server.connect().await;
+        guest.send(nick("alice")).await;
+        guest
+            .until_notice("Nickname alice is registered, please use /login <nickname> <password>")
+            .await;
+
+        let _carol = server.guest("carol").await;
+        guest.send(nick("carol")).await;
+        guest.until_notice(&in_use("carol")).await;
+    }
+
+    #[tokio::test]
+    async fn test_private_echo_and_receipts() {
+        let server = TestChat::new("session_receipts");
+        let mut alice = server.account("alice").await;
+        let mut alice_phone = server.account("alice").await;
+        let mut bob = server.account("bob").await;
+        let mut carol = server.guest("carol").await;
+
+        alice.send(private("bob", "hi")).await;
+        let id = bob.until_private("alice").await;
+        for sender in [&mut alice, &mut alice_phone] {
+            let received = sender.received().await;
+            assert_eq!(conversation(&received), vec!["private hi"]);
+            assert_eq!(receipts(&received), vec![(id, ReceiptStatus::Delivered)]);
+        }
+
+        // Only the first read is reported.
+        for _ in 0..2 {
+            bob.send(MessageType::Read(ReadMessage { ids: vec![id] }))
+                .await;
+        }
+        assert!(receipts(&bob.received().await).is_empty());
+        for sender in [&mut alice, &mut alice_phone] {
+            assert_eq!(
+                receipts(&sender.received().await),
+                vec![(id, ReceiptStatus::Read)]
+            );
+        }
+        assert!(carol.received().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_oversize_message_closes_connection() {
+        let server = TestChat::new("session_oversize");
+        let mut client = server.connect().await;
+        let size = default_websocket_config().max_message_size.unwrap();
+        client.send(chat(&"a".repeat(size))).await;
+
+        assert_eq!(client.close_code().await, CloseCode::Size);
+    }
+
+    #[tokio::test]
+    async fn test_limit_replies() {
+        let server = TestChat::new("session_limits");
+        let mut client = server.connect().await;
+        client.send(nick("two words")).await;
+        client
+            .until_notice(&format!(
+                "Invalid nickname: two words, it must have 1 to {} characters without spaces and ':'",
+                MAX_NICKNAME_LEN
+            ))
+            .await;
+
+        let mut carol = server.guest("carol").await;
+        carol.send(chat(&"a".repeat(MAX_MESSAGE_LEN + 1))).await;
+        carol
+            .until_notice(&format!(
+                "Message is too long, {} characters, the limit is {}",
+                MAX_MESSAGE_LEN + 1,
+                MAX_MESSAGE_LEN
+            ))
+            .await;
+        carol
+            .send(private("carol", &"a".repeat(MAX_MESSAGE_LEN + 1)))
+            .await;
+        assert!(notices(&carol.received().await)[0].starts_with("Message is too long"));
+    }
+
+    #[tokio::test]
+    async fn test_binary_frame_on_json_connection_closes() {
+        let server = TestChat::new("session_binary");
+        let mut client = server.connect().await;
+        client
+            .send_frame(Message::Binary(vec![0xff, 0x00, 0xfe]))
+            .await;
+
+        assert_eq!(client.close_code().await, CloseCode::Unsupported);
+    }
+
+    #[tokio::test]
+    async fn test_garbage_only_clients_are_disconnected() {
+        let server = TestChat::new("session_garbage");
+        let mut client = server.connect().await;
+        let garbage = || Message::Text(String::from("garbage"));
+        for _ in 1..MAX_INVALID_MESSAGES {
+            client.send_frame(garbage()).await;
+        }
+        // A valid command starts the count again.
+        client.send(MessageType::Help(HelpMessage {})).await;
+        for _ in 0..MAX_INVALID_MESSAGES {
+            client.send_frame(garbage()).await;
+        }
+
+        for _ in 1..MAX_INVALID_MESSAGES {
+            assert!(matches!(client.recv().await, ServerMessage::Error(_)));
+        }
+        assert_eq!(client.notice().await, USAGE_MSG);
+        for _ in 1..MAX_INVALID_MESSAGES {
+            assert!(matches!(client.recv().await, ServerMessage::Error(_)));
+        }
+        assert!(
+            matches!(client.next_frame().await, Message::Close(Some(frame))
+            if frame.code == CloseCode::Policy && frame.reason == "Too many invalid messages")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_login_attempts_are_limited() {
+        let mut server = TestChat::new("session_login_limit");
+        server.state.login_limiter = Arc::new(IpRateLimiter::new(RateLimit::new(2, 0.001)));
+        let mut client = server.connect().await;
+        let login = |password: &str| {
+            MessageType::Login(LoginMessage {
+                nick: String::from("alice"),
+                password: password.to_string(),
+            })
+        };
+
+        for _ in 0..2 {
+            client.send(login("guess")).await;
+            assert_eq!(client.notice().await, "Invalid nickname or password");
         }
+        client.send(login(PASSWORD)).await;
+        assert_eq!(
+            client.notice().await,
+            "Too many login attempts, please try again later"
+        );
     }
 }